@@ -0,0 +1,319 @@
+// A multi-consumer channel, like tokio::sync::broadcast
+// Messages go to a ring buffer, every receiver reads every message at its own position. A sender
+// never waits, it overwrites the oldest message, so a slow receiver gets `Lagged(n)` and skips
+// the n messages it missed.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    task::{Context, Poll, Waker},
+};
+
+// the value is given back when there is no receiver
+#[derive(Debug, PartialEq)]
+pub struct SendError<T>(pub T);
+
+#[derive(Debug, PartialEq)]
+pub enum RecvError {
+    // all senders are dropped and every message is received
+    Closed,
+    // the receiver skipped this many messages, the next recv gets the oldest one left
+    Lagged(u64),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TryRecvError {
+    Empty,
+    Closed,
+    Lagged(u64),
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    // returns how many receivers will see the message
+    pub fn send(&self, v: T) -> Result<usize, SendError<T>> {
+        let mut inner = self.shared.inner.lock().unwrap();
+        if inner.receivers == 0 {
+            return Err(SendError(v));
+        }
+        let capacity = inner.buffer.len() as u64;
+        let slot = (inner.tail % capacity) as usize;
+        inner.buffer[slot] = Some(v);
+        inner.tail += 1;
+        let receivers = inner.receivers;
+        self.shared.notify(inner);
+        Ok(receivers)
+    }
+
+    // the receiver gets messages sent after it subscribed
+    pub fn subscribe(&self) -> Receiver<T> {
+        let mut inner = self.shared.inner.lock().unwrap();
+        inner.receivers += 1;
+        let next = inner.tail;
+        drop(inner);
+
+        Receiver {
+            shared: self.shared.clone(),
+            next,
+        }
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.shared.inner.lock().unwrap().receivers
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        let mut inner = self.shared.inner.lock().unwrap();
+        inner.senders += 1;
+        drop(inner);
+
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut inner = self.shared.inner.lock().unwrap();
+        inner.senders -= 1;
+        if inner.senders == 0 {
+            self.shared.notify(inner);
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    // position of the next message to read
+    next: u64,
+}
+
+impl<T: Clone> Receiver<T> {
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let inner = self.shared.inner.lock().unwrap();
+        inner.take(&mut self.next)
+    }
+
+    pub fn blocking_recv(&mut self) -> Result<T, RecvError> {
+        let mut inner = self.shared.inner.lock().unwrap();
+        loop {
+            match inner.take(&mut self.next) {
+                Ok(v) => return Ok(v),
+                Err(TryRecvError::Lagged(n)) => return Err(RecvError::Lagged(n)),
+                Err(TryRecvError::Closed) => return Err(RecvError::Closed),
+                Err(TryRecvError::Empty) => {
+                    inner = self.shared.have_item.wait(inner).unwrap();
+                }
+            }
+        }
+    }
+
+    pub fn recv(&mut self) -> Recv<'_, T> {
+        Recv { receiver: self }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut inner = self.shared.inner.lock().unwrap();
+        inner.receivers -= 1;
+    }
+}
+
+// future of `Receiver::recv`
+pub struct Recv<'a, T> {
+    receiver: &'a mut Receiver<T>,
+}
+
+impl<T: Clone> Future for Recv<'_, T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let receiver = &mut self.get_mut().receiver;
+        let mut inner = receiver.shared.inner.lock().unwrap();
+        match inner.take(&mut receiver.next) {
+            Ok(v) => Poll::Ready(Ok(v)),
+            Err(TryRecvError::Lagged(n)) => Poll::Ready(Err(RecvError::Lagged(n))),
+            Err(TryRecvError::Closed) => Poll::Ready(Err(RecvError::Closed)),
+            Err(TryRecvError::Empty) => {
+                // a future may be polled many times before it's woken
+                if !inner.wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                    inner.wakers.push(cx.waker().clone());
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+struct Inner<T> {
+    // ring buffer, the message at position `pos` is in `buffer[pos % capacity]`
+    buffer: Vec<Option<T>>,
+    // position of the next message to send
+    tail: u64,
+    senders: usize,
+    receivers: usize,
+    // async receivers waiting for a message
+    wakers: Vec<Waker>,
+}
+
+impl<T: Clone> Inner<T> {
+    // the message at position `next`, and move `next` ahead
+    fn take(&self, next: &mut u64) -> Result<T, TryRecvError> {
+        let capacity = self.buffer.len() as u64;
+        let oldest = self.tail.saturating_sub(capacity);
+        if *next < oldest {
+            let lagged = oldest - *next;
+            *next = oldest;
+            return Err(TryRecvError::Lagged(lagged));
+        }
+        if *next == self.tail {
+            return match self.senders {
+                0 => Err(TryRecvError::Closed),
+                _ => Err(TryRecvError::Empty),
+            };
+        }
+
+        let slot = (*next % capacity) as usize;
+        *next += 1;
+        Ok(self.buffer[slot].clone().unwrap())
+    }
+}
+
+struct Shared<T> {
+    inner: Mutex<Inner<T>>,
+    have_item: Condvar,
+}
+
+impl<T> Shared<T> {
+    fn notify(&self, mut inner: MutexGuard<'_, Inner<T>>) {
+        let wakers = std::mem::take(&mut inner.wakers);
+        drop(inner);
+
+        self.have_item.notify_all();
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "capacity must be positive");
+    let inner = Inner {
+        buffer: (0..capacity).map(|_| None).collect(),
+        tail: 0,
+        senders: 1,
+        receivers: 1,
+        wakers: Vec::new(),
+    };
+    let shared = Shared {
+        inner: Mutex::new(inner),
+        have_item: Condvar::new(),
+    };
+    let shared = Arc::new(shared);
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared, next: 0 },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use std::time;
+
+    use super::*;
+
+    #[test]
+    fn works() {
+        let (tx, mut rx1) = channel(4);
+        let mut rx2 = tx.subscribe();
+        assert_eq!(tx.send(1), Ok(2));
+        assert_eq!(tx.send(2), Ok(2));
+        assert_eq!(rx1.try_recv(), Ok(1));
+        assert_eq!(rx1.try_recv(), Ok(2));
+        assert_eq!(rx1.try_recv(), Err(TryRecvError::Empty));
+        assert_eq!(rx2.try_recv(), Ok(1));
+
+        // a new subscriber starts from the tail
+        let mut rx3 = tx.subscribe();
+        assert_eq!(rx3.try_recv(), Err(TryRecvError::Empty));
+        tx.send(3).unwrap();
+        assert_eq!(rx3.try_recv(), Ok(3));
+        assert_eq!(rx2.try_recv(), Ok(2));
+        assert_eq!(rx2.try_recv(), Ok(3));
+    }
+
+    #[test]
+    fn lagged() {
+        let (tx, mut rx) = channel(2);
+        for n in 0..5 {
+            tx.send(n).unwrap();
+        }
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Lagged(3)));
+        assert_eq!(rx.try_recv(), Ok(3));
+        assert_eq!(rx.try_recv(), Ok(4));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn recv_wait_and_notify() {
+        let (tx, mut rx1) = channel(4);
+        let mut rx2 = tx.subscribe();
+        let handles: Vec<_> = vec![
+            thread::spawn(move || (rx1.blocking_recv(), rx1.blocking_recv())),
+            thread::spawn(move || (rx2.blocking_recv(), rx2.blocking_recv())),
+        ];
+        thread::sleep(time::Duration::from_millis(5));
+        tx.send(1).unwrap();
+        drop(tx);
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), (Ok(1), Err(RecvError::Closed)));
+        }
+    }
+
+    #[tokio::test]
+    async fn recv_async() {
+        let (tx, mut rx) = channel(1);
+        let handle = tokio::spawn(async move {
+            let mut received = Vec::new();
+            loop {
+                match rx.recv().await {
+                    Ok(v) => received.push(Ok(v)),
+                    Err(RecvError::Closed) => return received,
+                    Err(e) => received.push(Err(e)),
+                }
+            }
+        });
+        tokio::time::sleep(time::Duration::from_millis(5)).await;
+        tx.send(1).unwrap();
+        tokio::time::sleep(time::Duration::from_millis(5)).await;
+        tx.send(2).unwrap();
+        tx.send(3).unwrap();
+        drop(tx);
+        assert_eq!(
+            handle.await.unwrap(),
+            vec![Ok(1), Err(RecvError::Lagged(1)), Ok(3)]
+        );
+    }
+
+    #[test]
+    fn rx_close() {
+        let (tx, rx) = channel(1);
+        drop(rx);
+        assert_eq!(tx.send(42), Err(SendError(42)));
+        let mut rx = tx.subscribe();
+        assert_eq!(tx.receiver_count(), 1);
+        tx.send(43).unwrap();
+        assert_eq!(rx.try_recv(), Ok(43));
+    }
+}
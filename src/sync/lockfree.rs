@@ -0,0 +1,721 @@
+// A lock-free unbounded mpsc channel, the atomic flavors listed in `mpsc.rs`
+// Senders never lock or wait. When the queue is empty the receiver parks its thread, and the
+// sender which moves `state` from PARKED to UNPARKING unparks it. The receiver only writes its
+// thread while `state` is IDLE, so no sender reads it then.
+//  - list: Vyukov's intrusive MPSC queue, a node per message
+//  - block: a linked list of blocks of BLOCK_CAP slots, an allocation per BLOCK_CAP messages
+// Memory is only freed by the receiver. A list node is freed when the receiver moves past it, a
+// sender never touches a node after linking it. A block may still be seen by a sender which
+// loaded it as the tail before, so it's retired, and freed once every sender which started
+// pushing before it was retired is done. Senders count themselves in the current epoch, the
+// receiver moves to the next epoch and frees the retired blocks when the last one is empty.
+// The receiver has `recv_timeout`, `recv_deadline`, `iter` and `try_iter` like `mpsc.rs`.
+// Left out:
+//  - `try_send`: the channel is unbounded, `send` never blocks already
+//  - `closed`: it would make a sender wait for the receiver
+//  - `recv_async` and `Stream`: a sender would have to wake a waker, which needs a lock or a
+//    waker slot of its own, the receiver only parks a thread here
+
+use std::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    ptr,
+    sync::{
+        atomic::{self, AtomicBool, AtomicPtr, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::{self, Thread},
+    time::{Duration, Instant},
+};
+
+const BLOCK_CAP: usize = 32;
+
+// the receiver's `state`
+const IDLE: usize = 0;
+// parked, or about to check the queue once more and park
+const PARKED: usize = 1;
+// a sender is unparking it
+const UNPARKING: usize = 2;
+
+// the receiver is dropped
+#[derive(Debug, PartialEq)]
+pub struct SendError<T>(pub T);
+
+#[derive(Debug, PartialEq)]
+pub enum TryRecvError {
+    Empty,
+    Disconnected,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RecvTimeoutError {
+    Timeout,
+    Disconnected,
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    pub fn send(&self, v: T) -> Result<(), SendError<T>> {
+        if !self.shared.receiver.load(Ordering::Acquire) {
+            return Err(SendError(v));
+        }
+        self.shared.queue.push(v);
+        self.shared.unpark();
+        Ok(())
+    }
+
+    // the receiver is dropped
+    pub fn is_closed(&self) -> bool {
+        !self.shared.receiver.load(Ordering::Acquire)
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.shared.unpark();
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    // None when all senders are dropped and the queue is empty
+    pub fn recv(&mut self) -> Option<T> {
+        self.recv_until(None).ok()
+    }
+
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.recv_until(Some(Instant::now() + timeout))
+    }
+
+    pub fn recv_deadline(&mut self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.recv_until(Some(deadline))
+    }
+
+    // blocks for each item, ends when all senders are dropped
+    pub fn iter(&mut self) -> Iter<'_, T> {
+        Iter { receiver: self }
+    }
+
+    // takes the items already sent, without blocking
+    pub fn try_iter(&mut self) -> TryIter<'_, T> {
+        TryIter { receiver: self }
+    }
+
+    fn recv_until(&mut self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        loop {
+            match self.try_recv() {
+                Ok(v) => {
+                    self.shared.idle();
+                    return Ok(v);
+                }
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) if self.shared.state.load(Ordering::SeqCst) == PARKED => {
+                    // a spurious wake up keeps the state PARKED, an unpark makes it IDLE
+                    match deadline {
+                        Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                            Some(timeout) if timeout > Duration::ZERO => {
+                                thread::park_timeout(timeout)
+                            }
+                            _ => {
+                                // a later send may still unpark, the next park returns early
+                                self.shared.idle();
+                                return Err(RecvTimeoutError::Timeout);
+                            }
+                        },
+                        None => thread::park(),
+                    }
+                }
+                Err(TryRecvError::Empty)
+                    if self.shared.state.load(Ordering::SeqCst) == UNPARKING =>
+                {
+                    // the unpark may have been taken by the last park already, so parking now
+                    // could miss the next send, which sees the state UNPARKING, not PARKED
+                    thread::yield_now();
+                }
+                Err(TryRecvError::Empty) => {
+                    // check again after the state is PARKED, a sender before this didn't unpark
+                    // SAFETY:
+                    // the state is IDLE, no sender reads the thread until it's PARKED
+                    unsafe { *self.shared.thread.get() = Some(thread::current()) };
+                    self.shared.state.store(PARKED, Ordering::SeqCst);
+                    atomic::fence(Ordering::SeqCst);
+                }
+            }
+        }
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        // SAFETY:
+        // `&mut self`, the receiver is the only consumer
+        if let Some(v) = unsafe { self.shared.queue.pop() } {
+            return Ok(v);
+        }
+        if self.shared.senders.load(Ordering::SeqCst) == 0 {
+            // every push is finished, look again for one finished after the pop above
+            return unsafe { self.shared.queue.pop() }.ok_or(TryRecvError::Disconnected);
+        }
+        Err(TryRecvError::Empty)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.receiver.store(false, Ordering::Release);
+    }
+}
+
+pub struct Iter<'a, T> {
+    receiver: &'a mut Receiver<T>,
+}
+
+impl<T> Iterator for Iter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.receiver.recv()
+    }
+}
+
+pub struct TryIter<'a, T> {
+    receiver: &'a mut Receiver<T>,
+}
+
+impl<T> Iterator for TryIter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+struct Shared<T> {
+    queue: Queue<T>,
+    senders: AtomicUsize,
+    // the receiver is alive
+    receiver: AtomicBool,
+    // IDLE, PARKED or UNPARKING
+    state: AtomicUsize,
+    // the receiver's thread, written by the receiver when IDLE, read by a sender when UNPARKING
+    thread: UnsafeCell<Option<Thread>>,
+}
+
+impl<T> Shared<T> {
+    fn unpark(&self) {
+        // Pairs with the fence in `Receiver::recv`: the receiver sees the item, or the sender
+        // sees the receiver parked.
+        atomic::fence(Ordering::SeqCst);
+        if self
+            .state
+            .compare_exchange(PARKED, UNPARKING, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+        {
+            // SAFETY:
+            // the receiver doesn't write the thread until the state is IDLE again
+            if let Some(thread) = unsafe { &*self.thread.get() } {
+                thread.unpark();
+            }
+            self.state.store(IDLE, Ordering::SeqCst);
+        }
+    }
+
+    // Called by the receiver when it stops waiting. A sender which is unparking it makes the
+    // state IDLE itself, its unpark makes the next park return early.
+    fn idle(&self) {
+        let _ = self
+            .state
+            .compare_exchange(PARKED, IDLE, Ordering::SeqCst, Ordering::SeqCst);
+    }
+}
+
+enum Queue<T> {
+    List(ListQueue<T>),
+    Block(BlockQueue<T>),
+}
+
+impl<T> Queue<T> {
+    fn push(&self, v: T) {
+        match self {
+            Queue::List(queue) => queue.push(v),
+            Queue::Block(queue) => queue.push(v),
+        }
+    }
+
+    // SAFETY:
+    // only called by the single receiver
+    unsafe fn pop(&self) -> Option<T> {
+        match self {
+            Queue::List(queue) => queue.pop(),
+            Queue::Block(queue) => queue.pop(),
+        }
+    }
+}
+
+// Vyukov's queue. Senders swap `head` to their node and link the previous head to it, the
+// receiver follows the links from `tail`, the node it has read last.
+struct ListQueue<T> {
+    head: AtomicPtr<Node<T>>,
+    tail: UnsafeCell<*mut Node<T>>,
+}
+
+struct Node<T> {
+    next: AtomicPtr<Node<T>>,
+    value: Option<T>,
+}
+
+impl<T> Node<T> {
+    fn new(value: Option<T>) -> *mut Self {
+        let node = Node {
+            next: AtomicPtr::new(ptr::null_mut()),
+            value,
+        };
+        Box::into_raw(Box::new(node))
+    }
+}
+
+impl<T> ListQueue<T> {
+    fn new() -> Self {
+        // the receiver always holds a read node, a stub at first
+        let stub = Node::new(None);
+        ListQueue {
+            head: AtomicPtr::new(stub),
+            tail: UnsafeCell::new(stub),
+        }
+    }
+
+    fn push(&self, v: T) {
+        let node = Node::new(Some(v));
+        let prev = self.head.swap(node, Ordering::AcqRel);
+        // SAFETY:
+        // the receiver frees `prev` only after moving past it, which needs the link set here
+        unsafe { (*prev).next.store(node, Ordering::Release) };
+    }
+
+    // SAFETY:
+    // only called by the single receiver
+    unsafe fn pop(&self) -> Option<T> {
+        let tail = *self.tail.get();
+        let next = (*tail).next.load(Ordering::Acquire);
+        if next.is_null() {
+            // empty, or a sender swapped `head` and hasn't linked it yet
+            return None;
+        }
+        *self.tail.get() = next;
+        drop(Box::from_raw(tail));
+        (*next).value.take()
+    }
+}
+
+impl<T> Drop for ListQueue<T> {
+    fn drop(&mut self) {
+        // SAFETY:
+        // no sender or receiver is left
+        unsafe {
+            while self.pop().is_some() {}
+            drop(Box::from_raw(*self.tail.get()));
+        }
+    }
+}
+
+// Senders claim a slot in the tail block, the one which claims past the end links the next block.
+// The receiver reads the slots in order and retires a block when it moves to the next one.
+struct BlockQueue<T> {
+    tail: AtomicPtr<Block<T>>,
+    // only moved by the receiver, a sender counts itself in `pushing[epoch % 2]`
+    epoch: AtomicUsize,
+    // senders in `push` by epoch, they may hold a block retired before the epoch moved
+    pushing: [AtomicUsize; 2],
+    head: UnsafeCell<Head<T>>,
+}
+
+// the receiver's side of `BlockQueue`
+struct Head<T> {
+    block: *mut Block<T>,
+    // the next slot to read
+    index: usize,
+    // blocks the receiver has left since the epoch moved
+    retired: Vec<*mut Block<T>>,
+    // blocks retired before the epoch moved, freed once the previous epoch has no sender
+    freeing: Vec<*mut Block<T>>,
+}
+
+struct Block<T> {
+    slots: [Slot<T>; BLOCK_CAP],
+    // slots claimed by senders, goes over BLOCK_CAP once the block is full
+    claimed: AtomicUsize,
+    next: AtomicPtr<Block<T>>,
+}
+
+struct Slot<T> {
+    value: UnsafeCell<MaybeUninit<T>>,
+    // the value is written
+    ready: AtomicBool,
+}
+
+impl<T> Block<T> {
+    fn new() -> *mut Self {
+        let block = Block {
+            slots: std::array::from_fn(|_| Slot {
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+                ready: AtomicBool::new(false),
+            }),
+            claimed: AtomicUsize::new(0),
+            next: AtomicPtr::new(ptr::null_mut()),
+        };
+        Box::into_raw(Box::new(block))
+    }
+}
+
+impl<T> BlockQueue<T> {
+    fn new() -> Self {
+        let block = Block::new();
+        let head = Head {
+            block,
+            index: 0,
+            retired: Vec::new(),
+            freeing: Vec::new(),
+        };
+        BlockQueue {
+            tail: AtomicPtr::new(block),
+            epoch: AtomicUsize::new(0),
+            pushing: [AtomicUsize::new(0), AtomicUsize::new(0)],
+            head: UnsafeCell::new(head),
+        }
+    }
+
+    fn push(&self, v: T) {
+        let pushing = loop {
+            let epoch = self.epoch.load(Ordering::SeqCst);
+            let pushing = &self.pushing[epoch % 2];
+            pushing.fetch_add(1, Ordering::SeqCst);
+            // Counted in the epoch it loaded: the receiver waits for this sender before it
+            // frees the blocks retired in that epoch. Otherwise it may have freed them already.
+            if self.epoch.load(Ordering::SeqCst) == epoch {
+                break pushing;
+            }
+            pushing.fetch_sub(1, Ordering::SeqCst);
+        };
+        loop {
+            let block = self.tail.load(Ordering::SeqCst);
+            // SAFETY:
+            // The tail is loaded after this sender is counted in the epoch, a block retired
+            // before the epoch was moved past by the tail already, and a block retired after is
+            // only freed once the count is zero.
+            let block_ref = unsafe { &*block };
+            let index = block_ref.claimed.fetch_add(1, Ordering::AcqRel);
+            if index < BLOCK_CAP {
+                let slot = &block_ref.slots[index];
+                // SAFETY:
+                // the slot is claimed by this sender only, the receiver waits for `ready`
+                unsafe { (*slot.value.get()).write(v) };
+                slot.ready.store(true, Ordering::Release);
+                break;
+            }
+
+            // the block is full, link the next one if no other sender did, and move the tail
+            let mut next = block_ref.next.load(Ordering::Acquire);
+            if next.is_null() {
+                let new = Block::new();
+                next = match block_ref.next.compare_exchange(
+                    ptr::null_mut(),
+                    new,
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                ) {
+                    Ok(_) => new,
+                    Err(linked) => {
+                        // SAFETY:
+                        // the new block was never shared
+                        unsafe { drop(Box::from_raw(new)) };
+                        linked
+                    }
+                };
+            }
+            let _ = self
+                .tail
+                .compare_exchange(block, next, Ordering::SeqCst, Ordering::SeqCst);
+        }
+        pushing.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    // SAFETY:
+    // only called by the single receiver
+    unsafe fn pop(&self) -> Option<T> {
+        let head = &mut *self.head.get();
+        if head.index == BLOCK_CAP {
+            let next = (*head.block).next.load(Ordering::Acquire);
+            if next.is_null() {
+                return None;
+            }
+            // The tail moves past the block before it's retired, so a sender which starts
+            // pushing after this can't load it.
+            let _ =
+                self.tail
+                    .compare_exchange(head.block, next, Ordering::SeqCst, Ordering::SeqCst);
+            head.retired.push(head.block);
+            head.block = next;
+            head.index = 0;
+        }
+        self.reclaim(head);
+
+        let slot = &(*head.block).slots[head.index];
+        if !slot.ready.load(Ordering::Acquire) {
+            // empty, or a sender claimed the slot and hasn't written it yet
+            return None;
+        }
+        head.index += 1;
+        Some((*slot.value.get()).assume_init_read())
+    }
+
+    // SAFETY:
+    // only called by the single receiver
+    unsafe fn reclaim(&self, head: &mut Head<T>) {
+        let epoch = self.epoch.load(Ordering::SeqCst);
+        // The senders of the previous epoch are done, none of them holds a block in `freeing`,
+        // and the senders of this epoch loaded the tail after it moved past them.
+        if !head.freeing.is_empty() && self.pushing[(epoch + 1) % 2].load(Ordering::SeqCst) == 0 {
+            for block in head.freeing.drain(..) {
+                drop(Box::from_raw(block));
+            }
+        }
+        // Only one batch at a time, moving the epoch again would count new senders with the
+        // ones `freeing` waits for. Pushes never wait, so the previous epoch empties soon.
+        if head.freeing.is_empty() && !head.retired.is_empty() {
+            std::mem::swap(&mut head.freeing, &mut head.retired);
+            self.epoch.store(epoch + 1, Ordering::SeqCst);
+        }
+    }
+}
+
+impl<T> Drop for BlockQueue<T> {
+    fn drop(&mut self) {
+        // SAFETY:
+        // no sender or receiver is left, values not read are dropped, then every block is freed
+        unsafe {
+            while self.pop().is_some() {}
+            let head = &mut *self.head.get();
+            for block in head.retired.drain(..).chain(head.freeing.drain(..)) {
+                drop(Box::from_raw(block));
+            }
+            let mut block = head.block;
+            while !block.is_null() {
+                let next = (*block).next.load(Ordering::Acquire);
+                drop(Box::from_raw(block));
+                block = next;
+            }
+        }
+    }
+}
+
+// SAFETY:
+// values move from the senders' threads to the receiver's, and the receiver's side is only
+// used by the single `Receiver`
+unsafe impl<T: Send> Send for ListQueue<T> {}
+unsafe impl<T: Send> Sync for ListQueue<T> {}
+unsafe impl<T: Send> Send for BlockQueue<T> {}
+unsafe impl<T: Send> Sync for BlockQueue<T> {}
+// the thread is only shared as the state allows
+unsafe impl<T: Send> Send for Shared<T> {}
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+// the block flavor
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    new(Queue::Block(BlockQueue::new()))
+}
+
+pub fn list_channel<T>() -> (Sender<T>, Receiver<T>) {
+    new(Queue::List(ListQueue::new()))
+}
+
+fn new<T>(queue: Queue<T>) -> (Sender<T>, Receiver<T>) {
+    let shared = Shared {
+        queue,
+        senders: AtomicUsize::new(1),
+        receiver: AtomicBool::new(true),
+        state: AtomicUsize::new(IDLE),
+        thread: UnsafeCell::new(None),
+    };
+    let shared = Arc::new(shared);
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::time;
+
+    use super::*;
+
+    type New<T> = fn() -> (Sender<T>, Receiver<T>);
+
+    fn flavors<T>() -> Vec<New<T>> {
+        vec![channel, list_channel]
+    }
+
+    #[test]
+    fn works() {
+        for new in flavors() {
+            let (tx, mut rx) = new();
+            assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+            for n in 0..100 {
+                tx.send(n).unwrap();
+            }
+            assert_eq!(
+                (0..100).map(|_| rx.recv().unwrap()).collect::<Vec<_>>(),
+                (0..100).collect::<Vec<_>>()
+            );
+            drop(tx);
+            assert_eq!(rx.recv(), None);
+            assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+        }
+    }
+
+    #[test]
+    fn recv_wait_and_notify() {
+        for new in flavors() {
+            let (tx, mut rx) = new();
+            thread::spawn(move || {
+                for n in 0..3 {
+                    thread::sleep(time::Duration::from_millis(2));
+                    tx.send(n).unwrap();
+                }
+            });
+            assert_eq!(rx.recv(), Some(0));
+            assert_eq!(rx.recv(), Some(1));
+            assert_eq!(rx.recv(), Some(2));
+            assert_eq!(rx.recv(), None);
+        }
+    }
+
+    #[test]
+    fn many_senders() {
+        for new in flavors() {
+            let (tx, mut rx) = new();
+            let handles: Vec<_> = (0..4)
+                .map(|id| {
+                    let tx = tx.clone();
+                    thread::spawn(move || {
+                        for n in 0..1000 {
+                            tx.send((id, n)).unwrap();
+                        }
+                    })
+                })
+                .collect();
+            drop(tx);
+
+            // messages of one sender keep their order
+            let mut last = [None; 4];
+            let mut count = 0;
+            while let Some((id, n)) = rx.recv() {
+                assert!(last[id].is_none_or(|last| last < n));
+                last[id] = Some(n);
+                count += 1;
+            }
+            assert_eq!(count, 4000);
+            for handle in handles {
+                handle.join().unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn unread_values_are_dropped() {
+        for new in flavors() {
+            let value = Arc::new(());
+            let (tx, mut rx) = new();
+            for _ in 0..100 {
+                tx.send(value.clone()).unwrap();
+            }
+            rx.recv().unwrap();
+            drop(tx);
+            drop(rx);
+            assert_eq!(Arc::strong_count(&value), 1);
+        }
+    }
+
+    #[test]
+    fn rx_close() {
+        for new in flavors() {
+            let (tx, rx) = new();
+            assert!(!tx.is_closed());
+            drop(rx);
+            assert!(tx.is_closed());
+            assert_eq!(tx.send(42), Err(SendError(42)));
+        }
+    }
+
+    #[test]
+    fn retired_blocks_are_freed() {
+        let (tx, mut rx) = channel();
+        let shared = rx.shared.clone();
+        let queue = match &shared.queue {
+            Queue::Block(queue) => queue,
+            Queue::List(_) => unreachable!(),
+        };
+        for n in 0..BLOCK_CAP * 8 {
+            tx.send(n).unwrap();
+        }
+        for n in 0..BLOCK_CAP * 8 {
+            // a sender is always pushing, like senders on other threads sending all the time
+            let pushing = &queue.pushing[queue.epoch.load(Ordering::SeqCst) % 2];
+            pushing.fetch_add(1, Ordering::SeqCst);
+            assert_eq!(rx.recv(), Some(n));
+            pushing.fetch_sub(1, Ordering::SeqCst);
+        }
+        // SAFETY:
+        // the receiver isn't used while reading its side
+        let head = unsafe { &*queue.head.get() };
+        // only the block retired last waits for the senders
+        assert!(head.retired.len() + head.freeing.len() <= 1);
+    }
+
+    #[test]
+    fn recv_timeout_and_iterators() {
+        for new in flavors() {
+            let (tx, mut rx) = new();
+            assert_eq!(
+                rx.recv_timeout(time::Duration::from_millis(1)),
+                Err(RecvTimeoutError::Timeout)
+            );
+            for n in 0..3 {
+                tx.send(n).unwrap();
+            }
+            assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![0, 1, 2]);
+
+            thread::spawn(move || {
+                for n in 3..6 {
+                    thread::sleep(time::Duration::from_millis(2));
+                    tx.send(n).unwrap();
+                }
+            });
+            assert_eq!(rx.recv_timeout(time::Duration::from_secs(1)), Ok(3));
+            let deadline = Instant::now() + time::Duration::from_secs(1);
+            assert_eq!(rx.recv_deadline(deadline), Ok(4));
+            assert_eq!(rx.iter().collect::<Vec<_>>(), vec![5]);
+            assert_eq!(
+                rx.recv_timeout(time::Duration::from_millis(1)),
+                Err(RecvTimeoutError::Disconnected)
+            );
+        }
+    }
+}
@@ -1 +1,6 @@
+mod broadcast;
+mod lockfree;
+mod mpmc;
 mod mpsc;
+mod oneshot;
+pub mod watch;
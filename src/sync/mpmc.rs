@@ -0,0 +1,421 @@
+// A multi-producer multi-consumer channel, a work queue for a pool of threads
+// Like `mpsc.rs`, but the receiver is cloneable and each message goes to one of the receivers.
+// A receiver moves a batch of the queue into its buffer to lock less. The batch is its share of
+// the queue (len / receivers), at most `MAX_BATCH` items, and only that receiver takes from it:
+// while a worker is busy, the idle ones wait for new items even if its batch isn't drained. The
+// batch goes back to the queue when the receiver is dropped.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+const MAX_BATCH: usize = 16;
+
+// all receivers are dropped
+#[derive(Debug, PartialEq)]
+pub struct SendError<T>(pub T);
+
+#[derive(Debug, PartialEq)]
+pub enum TrySendError<T> {
+    Full(T),
+    Disconnected(T),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TryRecvError {
+    Empty,
+    Disconnected,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RecvTimeoutError {
+    Timeout,
+    Disconnected,
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    pub fn send(&self, v: T) -> Result<(), SendError<T>> {
+        let mut inner = self.shared.inner.lock().unwrap();
+        while inner.is_full() && inner.receivers > 0 {
+            inner = self.shared.have_space.wait(inner).unwrap();
+        }
+        if inner.receivers == 0 {
+            return Err(SendError(v));
+        }
+        inner.queue.push_back(v);
+        self.shared.have_item.notify_one();
+        Ok(())
+    }
+
+    pub fn try_send(&self, v: T) -> Result<(), TrySendError<T>> {
+        let mut inner = self.shared.inner.lock().unwrap();
+        if inner.receivers == 0 {
+            return Err(TrySendError::Disconnected(v));
+        }
+        if inner.is_full() {
+            return Err(TrySendError::Full(v));
+        }
+        inner.queue.push_back(v);
+        self.shared.have_item.notify_one();
+        Ok(())
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.shared.inner.lock().unwrap().receivers
+    }
+
+    // all receivers are dropped
+    pub fn is_closed(&self) -> bool {
+        self.receiver_count() == 0
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        let mut inner = self.shared.inner.lock().unwrap();
+        inner.senders += 1;
+        drop(inner);
+
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut inner = self.shared.inner.lock().unwrap();
+        inner.senders -= 1;
+        let is_last = inner.senders == 0;
+        drop(inner);
+        if is_last {
+            // every waiting receiver sees the disconnect
+            self.shared.have_item.notify_all();
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    buffer: VecDeque<T>,
+    // items moved to the buffer the last time, they take capacity until it's drained
+    buffered: usize,
+}
+
+impl<T> Receiver<T> {
+    // None when all senders are dropped and the queue is empty
+    pub fn recv(&mut self) -> Option<T> {
+        self.recv_until(None).ok()
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        self.recv_until(Some(Instant::now())).map_err(|e| match e {
+            RecvTimeoutError::Timeout => TryRecvError::Empty,
+            RecvTimeoutError::Disconnected => TryRecvError::Disconnected,
+        })
+    }
+
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.recv_until(Some(Instant::now() + timeout))
+    }
+
+    fn recv_until(&mut self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        if let Some(v) = self.buffer.pop_front() {
+            return Ok(v);
+        }
+
+        let mut inner = self.shared.inner.lock().unwrap();
+        self.shared.release(&mut inner, &mut self.buffered);
+        loop {
+            if let Some(v) = inner.queue.pop_front() {
+                // the rest is shared with the other receivers, rounded down so a few items
+                // are left to whoever asks next
+                let share = (inner.queue.len() / inner.receivers).min(MAX_BATCH);
+                self.buffer.extend(inner.queue.drain(..share));
+                if inner.capacity.is_some() {
+                    self.buffered = share;
+                    inner.buffered += share;
+                    self.shared.have_space.notify_one();
+                }
+                if !inner.queue.is_empty() {
+                    self.shared.have_item.notify_one();
+                }
+                return Ok(v);
+            }
+            if inner.senders == 0 {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            // the wait may wake up early, so the time left is computed again each time
+            inner = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(timeout) if timeout > Duration::ZERO => {
+                        self.shared
+                            .have_item
+                            .wait_timeout(inner, timeout)
+                            .unwrap()
+                            .0
+                    }
+                    _ => return Err(RecvTimeoutError::Timeout),
+                },
+                None => self.shared.have_item.wait(inner).unwrap(),
+            };
+        }
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        let mut inner = self.shared.inner.lock().unwrap();
+        inner.receivers += 1;
+        drop(inner);
+
+        Receiver {
+            shared: self.shared.clone(),
+            buffer: VecDeque::new(),
+            buffered: 0,
+        }
+    }
+}
+
+// items left in the buffer go back to the front of the queue for the other receivers
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut inner = self.shared.inner.lock().unwrap();
+        self.shared.release(&mut inner, &mut self.buffered);
+        while let Some(v) = self.buffer.pop_back() {
+            inner.queue.push_front(v);
+        }
+        inner.receivers -= 1;
+        let has_item = !inner.queue.is_empty();
+        drop(inner);
+
+        if has_item {
+            self.shared.have_item.notify_all();
+        }
+        self.shared.have_space.notify_all();
+    }
+}
+
+struct Inner<T> {
+    queue: VecDeque<T>,
+    senders: usize,
+    receivers: usize,
+    // None for the unbounded channel
+    capacity: Option<usize>,
+    // items in the receivers' buffers
+    buffered: usize,
+}
+
+impl<T> Inner<T> {
+    fn is_full(&self) -> bool {
+        match self.capacity {
+            Some(capacity) => self.queue.len() + self.buffered >= capacity,
+            None => false,
+        }
+    }
+}
+
+struct Shared<T> {
+    inner: Mutex<Inner<T>>,
+    have_item: Condvar,
+    have_space: Condvar,
+}
+
+impl<T> Shared<T> {
+    // a receiver's buffer is drained, its items don't take capacity anymore
+    fn release(&self, inner: &mut Inner<T>, buffered: &mut usize) {
+        if *buffered > 0 {
+            inner.buffered -= std::mem::take(buffered);
+            self.have_space.notify_all();
+        }
+    }
+}
+
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    new(None)
+}
+
+// panics when capacity is 0, this channel has no rendezvous flavor
+pub fn sync_channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "capacity must be positive");
+    new(Some(capacity))
+}
+
+fn new<T>(capacity: Option<usize>) -> (Sender<T>, Receiver<T>) {
+    let inner = Inner {
+        queue: VecDeque::new(),
+        senders: 1,
+        receivers: 1,
+        capacity,
+        buffered: 0,
+    };
+    let shared = Shared {
+        inner: Mutex::new(inner),
+        have_item: Condvar::new(),
+        have_space: Condvar::new(),
+    };
+    let shared = Arc::new(shared);
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver {
+            shared,
+            buffer: VecDeque::new(),
+            buffered: 0,
+        },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use std::time;
+
+    use super::*;
+
+    #[test]
+    fn works() {
+        let (tx, mut rx1) = channel();
+        let mut rx2 = rx1.clone();
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        // rx1 leaves the second item to rx2
+        assert_eq!(rx1.recv(), Some(1));
+        assert_eq!(rx2.recv(), Some(2));
+        assert_eq!(rx1.try_recv(), Err(TryRecvError::Empty));
+        drop(tx);
+        assert_eq!(rx1.recv(), None);
+        assert_eq!(rx2.recv(), None);
+    }
+
+    #[test]
+    fn workers_share_the_queue() {
+        let (tx, rx) = channel();
+        for n in 0..100 {
+            tx.send(n).unwrap();
+        }
+        drop(tx);
+        let receivers: Vec<_> = (0..4).map(|_| rx.clone()).collect();
+        drop(rx);
+        let handles: Vec<_> = receivers
+            .into_iter()
+            .map(|mut rx| {
+                thread::spawn(move || {
+                    let mut received = Vec::new();
+                    while let Some(v) = rx.recv() {
+                        received.push(v);
+                        thread::sleep(time::Duration::from_micros(100));
+                    }
+                    received
+                })
+            })
+            .collect();
+
+        let mut all = Vec::new();
+        for handle in handles {
+            let received = handle.join().unwrap();
+            // the first worker takes at most its share
+            assert!(!received.is_empty() && received.len() <= 50);
+            all.extend(received);
+        }
+        all.sort_unstable();
+        assert_eq!(all, (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn batch_is_capped() {
+        let (tx, mut rx1) = channel();
+        let mut rx2 = rx1.clone();
+        for n in 0..100 {
+            tx.send(n).unwrap();
+        }
+        assert_eq!(rx1.recv(), Some(0));
+        // rx1 holds 1..=MAX_BATCH, rx2 takes the next one and its own batch
+        assert_eq!(rx2.recv(), Some(MAX_BATCH + 1));
+    }
+
+    #[test]
+    fn busy_worker_holds_its_batch() {
+        let (tx, mut rx) = channel();
+        let mut busy = rx.clone();
+        for n in 0..10 {
+            tx.send(n).unwrap();
+        }
+        drop(tx);
+        let (started, wait_started) = std::sync::mpsc::channel();
+        let (unblock, blocked) = std::sync::mpsc::channel::<()>();
+        let handle = thread::spawn(move || {
+            let mut received = vec![busy.recv().unwrap()];
+            started.send(()).unwrap();
+            let _ = blocked.recv();
+            while let Some(v) = busy.recv() {
+                received.push(v);
+            }
+            received
+        });
+
+        // the busy worker holds 1..=4, the idle one gets the rest only
+        wait_started.recv().unwrap();
+        let received: Vec<_> = (0..5).map(|_| rx.recv().unwrap()).collect();
+        assert_eq!(received, vec![5, 6, 7, 8, 9]);
+        assert_eq!(
+            rx.recv_timeout(time::Duration::from_millis(5)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+
+        drop(unblock);
+        assert_eq!(handle.join().unwrap(), vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity must be positive")]
+    fn zero_capacity_panics() {
+        let _ = sync_channel::<i32>(0);
+    }
+
+    #[test]
+    fn sync_send_wait_and_notify() {
+        let (tx, mut rx) = sync_channel(2);
+        let handle = thread::spawn(move || {
+            for n in 1..=4 {
+                tx.send(n).unwrap();
+            }
+        });
+        thread::sleep(time::Duration::from_millis(5));
+        let received: Vec<_> = (0..4).map(|_| rx.recv().unwrap()).collect();
+        assert_eq!(received, vec![1, 2, 3, 4]);
+        handle.join().unwrap();
+        assert_eq!(
+            rx.recv_timeout(time::Duration::from_millis(1)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn rx_close() {
+        let (tx, mut rx1) = channel();
+        let rx2 = rx1.clone();
+        assert_eq!(tx.receiver_count(), 2);
+        for n in 0..4 {
+            tx.send(n).unwrap();
+        }
+        // rx1 buffers its share, and gives it back when dropped
+        assert_eq!(rx1.recv(), Some(0));
+        drop(rx1);
+        let mut rx2 = rx2;
+        let received: Vec<_> = (0..3).map(|_| rx2.recv().unwrap()).collect();
+        assert_eq!(received, vec![1, 2, 3]);
+
+        drop(rx2);
+        assert!(tx.is_closed());
+        assert_eq!(tx.send(4), Err(SendError(4)));
+        assert_eq!(tx.try_send(5), Err(TrySendError::Disconnected(5)));
+    }
+}
@@ -15,27 +15,165 @@
 
 use std::{
     collections::VecDeque,
-    sync::{Arc, Condvar, Mutex},
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    task::{Context, Poll, Waker},
+    thread::{self, Thread},
+    time::{Duration, Instant},
 };
 
+use futures_core::Stream;
+
 // 1. Unbounded channel, channel to create tx and rx, send, recv
 // 2. tx close
 // 3. recv buffer
+// 4. Bounded channel, sync_channel, send blocks when full, try_send
+// 5. Rendezvous channel, sync_channel(0), send blocks until the receiver takes the value
+// 6. rx close, send gives the value back
+// 7. try_recv, recv_timeout, recv_deadline
+// 8. Iterators, iter blocks until tx close, try_iter stops when empty
+// 9. Select, park the thread until one of many receivers is ready
+// 10. async recv and send with wakers, sync and async ends mix on one channel
+
+// the receiver is dropped
+#[derive(Debug, PartialEq)]
+pub struct SendError<T>(pub T);
+
+#[derive(Debug, PartialEq)]
+pub enum TrySendError<T> {
+    Full(T),
+    Disconnected(T),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TryRecvError {
+    Empty,
+    Disconnected,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RecvTimeoutError {
+    Timeout,
+    Disconnected,
+}
 
 pub struct Sender<T> {
     shared: Arc<Shared<T>>,
 }
 
 impl<T> Sender<T> {
-    fn send(&self, v: T) {
+    fn send(&self, v: T) -> Result<(), SendError<T>> {
+        let mut inner = self.shared.inner.lock().unwrap();
+        let blocked = inner.is_full() && inner.receiver;
+        if blocked {
+            // a rendezvous receiver in `Select` is ready now
+            inner.blocked_senders += 1;
+            for thread in &inner.selectors {
+                thread.unpark();
+            }
+        }
+        while inner.is_full() && inner.receiver {
+            inner = self.shared.have_space.wait(inner).unwrap();
+        }
+        if blocked {
+            inner.blocked_senders -= 1;
+        }
+        if !inner.receiver {
+            return Err(SendError(v));
+        }
+        self.push(&mut inner, v);
+
+        let sent = inner.received + inner.queue.len();
+        while inner.capacity == Some(0) && inner.received < sent {
+            if !inner.receiver {
+                // the value is still in the queue, after the ones the receiver has taken
+                let index = sent - inner.received - 1;
+                return Err(SendError(inner.queue.remove(index).unwrap()));
+            }
+            inner = self.shared.have_space.wait(inner).unwrap();
+        }
+        Ok(())
+    }
+
+    fn try_send(&self, v: T) -> Result<(), TrySendError<T>> {
         let mut inner = self.shared.inner.lock().unwrap();
+        if !inner.receiver {
+            return Err(TrySendError::Disconnected(v));
+        }
+        if inner.is_full() {
+            return Err(TrySendError::Full(v));
+        }
+        self.push(&mut inner, v);
+        Ok(())
+    }
+
+    // Waits for space without blocking the thread. A rendezvous send completes once the value is
+    // handed to a waiting receiver, without waiting for it to be taken.
+    fn send_async(&self, v: T) -> SendAsync<'_, T> {
+        SendAsync {
+            sender: self,
+            value: Some(v),
+        }
+    }
+
+    // the receiver is dropped
+    fn is_closed(&self) -> bool {
+        !self.shared.inner.lock().unwrap().receiver
+    }
+
+    // block until the receiver is dropped
+    fn closed(&self) {
+        let mut inner = self.shared.inner.lock().unwrap();
+        while inner.receiver {
+            inner = self.shared.have_space.wait(inner).unwrap();
+        }
+    }
+
+    fn push(&self, inner: &mut Inner<T>, v: T) {
         let was_empty = inner.queue.is_empty();
         inner.queue.push_back(v);
-        drop(inner);
-
         if was_empty {
             self.shared.have_item.notify_one();
         }
+        for thread in &inner.selectors {
+            thread.unpark();
+        }
+        if let Some(waker) = inner.recv_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+// future of `Sender::send_async`
+struct SendAsync<'a, T> {
+    sender: &'a Sender<T>,
+    value: Option<T>,
+}
+
+// the value is never pinned
+impl<T> Unpin for SendAsync<'_, T> {}
+
+impl<T> Future for SendAsync<'_, T> {
+    type Output = Result<(), SendError<T>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let v = this.value.take().expect("polled after completion");
+        let mut inner = this.sender.shared.inner.lock().unwrap();
+        if !inner.receiver {
+            return Poll::Ready(Err(SendError(v)));
+        }
+        if inner.is_full() {
+            // a future may be polled many times before it's woken
+            if !inner.send_wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                inner.send_wakers.push(cx.waker().clone());
+            }
+            this.value = Some(v);
+            return Poll::Pending;
+        }
+        this.sender.push(&mut inner, v);
+        Poll::Ready(Ok(()))
     }
 }
 
@@ -56,6 +194,14 @@ impl<T> Drop for Sender<T> {
         let mut inner = self.shared.inner.lock().unwrap();
         inner.senders -= 1;
         let is_last = inner.senders == 0;
+        if is_last {
+            for thread in &inner.selectors {
+                thread.unpark();
+            }
+            if let Some(waker) = inner.recv_waker.take() {
+                waker.wake();
+            }
+        }
         drop(inner);
         if is_last {
             self.shared.have_item.notify_one();
@@ -66,50 +212,319 @@ impl<T> Drop for Sender<T> {
 struct Receiver<T> {
     shared: Arc<Shared<T>>,
     buffer: VecDeque<T>,
+    // the last `Stream` poll is pending, it may be abandoned without a future to drop
+    stream_pending: bool,
 }
 
 impl<T> Receiver<T> {
     fn recv(&mut self) -> Option<T> {
+        self.recv_until(None).ok()
+    }
+
+    fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        self.recv_until(Some(Instant::now())).map_err(|e| match e {
+            RecvTimeoutError::Timeout => TryRecvError::Empty,
+            RecvTimeoutError::Disconnected => TryRecvError::Disconnected,
+        })
+    }
+
+    fn recv_timeout(&mut self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.recv_until(Some(Instant::now() + timeout))
+    }
+
+    fn recv_deadline(&mut self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.recv_until(Some(deadline))
+    }
+
+    // blocks for each item, ends when all senders are dropped
+    fn iter(&mut self) -> Iter<'_, T> {
+        Iter { receiver: self }
+    }
+
+    // takes the items already sent, without blocking
+    fn try_iter(&mut self) -> TryIter<'_, T> {
+        TryIter { receiver: self }
+    }
+
+    fn recv_until(&mut self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
         if let Some(v) = self.buffer.pop_front() {
-            return Some(v);
+            return Ok(v);
         }
 
-        let mut inner = self.shared.inner.lock().unwrap();
+        let mut inner = self.shared.lock_receiver();
+        // the stream isn't polled anymore if the receiver is used this way
+        if std::mem::take(&mut self.stream_pending) {
+            inner.receiving = false;
+            inner.recv_waker = None;
+        }
         loop {
-            match inner.queue.pop_front() {
-                Some(v) => {
-                    if !inner.queue.is_empty() {
-                        std::mem::swap(&mut inner.queue, &mut self.buffer);
-                    }
-                    return Some(v);
-                }
-                None if inner.senders == 0 => return None,
-                None => {
-                    inner = self.shared.have_item.wait(inner).unwrap();
-                }
+            if let Some(v) = self.shared.take(&mut inner, &mut self.buffer) {
+                return Ok(v);
+            }
+            if inner.senders == 0 {
+                return Err(RecvTimeoutError::Disconnected);
             }
+            // the wait may wake up early, so the time left is computed again each time
+            let timeout = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(timeout) if timeout > Duration::ZERO => Some(timeout),
+                    // a blocked rendezvous sender hands off as soon as the receiver waits, like
+                    // an item already in the queue
+                    _ if inner.capacity == Some(0) && inner.blocked_senders > 0 => None,
+                    _ => return Err(RecvTimeoutError::Timeout),
+                },
+                None => None,
+            };
+
+            inner.receiving = true;
+            if inner.capacity == Some(0) {
+                self.shared.notify_space(&mut inner);
+            }
+            inner = match timeout {
+                Some(timeout) => {
+                    self.shared
+                        .have_item
+                        .wait_timeout(inner, timeout)
+                        .unwrap()
+                        .0
+                }
+                None => self.shared.have_item.wait(inner).unwrap(),
+            };
+            inner.receiving = false;
         }
     }
+
+    // None when all senders are dropped
+    fn recv_async(&mut self) -> RecvAsync<'_, T> {
+        RecvAsync { receiver: self }
+    }
+
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        if let Some(v) = self.buffer.pop_front() {
+            return Poll::Ready(Some(v));
+        }
+
+        let mut inner = self.shared.lock_receiver();
+        if let Some(v) = self.shared.take(&mut inner, &mut self.buffer) {
+            inner.receiving = false;
+            return Poll::Ready(Some(v));
+        }
+        if inner.senders == 0 {
+            inner.receiving = false;
+            return Poll::Ready(None);
+        }
+        inner.recv_waker = Some(cx.waker().clone());
+        // the task waits like a blocked receiver, so a rendezvous sender can hand off to it
+        if inner.capacity == Some(0) && !inner.receiving {
+            inner.receiving = true;
+            self.shared.notify_space(&mut inner);
+        }
+        Poll::Pending
+    }
+}
+
+// future of `Receiver::recv_async`
+struct RecvAsync<'a, T> {
+    receiver: &'a mut Receiver<T>,
+}
+
+impl<T> Future for RecvAsync<'_, T> {
+    type Output = Option<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.get_mut().receiver.poll_recv(cx)
+    }
+}
+
+// a cancelled recv isn't waiting anymore, a rendezvous sender must not hand off to it
+impl<T> Drop for RecvAsync<'_, T> {
+    fn drop(&mut self) {
+        let mut inner = self.receiver.shared.inner.lock().unwrap();
+        inner.receiving = false;
+        inner.recv_waker = None;
+    }
+}
+
+// the buffer is never pinned
+impl<T> Unpin for Receiver<T> {}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let this = self.get_mut();
+        let poll = this.poll_recv(cx);
+        this.stream_pending = poll.is_pending();
+        poll
+    }
+}
+
+// Iterators go through `recv`, so they take items from the buffer without locking
+struct Iter<'a, T> {
+    receiver: &'a mut Receiver<T>,
+}
+
+impl<T> Iterator for Iter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.receiver.recv()
+    }
+}
+
+struct TryIter<'a, T> {
+    receiver: &'a mut Receiver<T>,
+}
+
+impl<T> Iterator for TryIter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+struct IntoIter<T> {
+    receiver: Receiver<T>,
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.receiver.recv()
+    }
+}
+
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter { receiver: self }
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+// wakes senders waiting for space or in `closed`, sync or async
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut inner = self.shared.inner.lock().unwrap();
+        inner.receiver = false;
+        // a pending stream poll isn't waiting anymore
+        inner.receiving = false;
+        inner.recv_waker = None;
+        self.shared.notify_space(&mut inner);
+    }
 }
 
 struct Inner<T> {
     queue: VecDeque<T>,
     senders: usize,
+    // None for the unbounded channel
+    capacity: Option<usize>,
+    // items swapped to the receiver's buffer, they still take capacity until it's drained
+    buffered: usize,
+    // the receiver is alive
+    receiver: bool,
+    // the receiver is waiting for an item
+    receiving: bool,
+    // items the receiver has taken from the queue
+    received: usize,
+    // threads in `Select` waiting on this channel
+    selectors: Vec<Thread>,
+    // senders blocked in `send` until there is space, or a rendezvous receiver
+    blocked_senders: usize,
+    // the task awaiting the receiver
+    recv_waker: Option<Waker>,
+    // tasks awaiting space to send
+    send_wakers: Vec<Waker>,
+}
+
+impl<T> Inner<T> {
+    fn is_full(&self) -> bool {
+        match self.capacity {
+            // rendezvous, a value is handed to a waiting receiver only
+            Some(0) => !self.receiving || !self.queue.is_empty(),
+            Some(capacity) => self.queue.len() + self.buffered >= capacity,
+            None => false,
+        }
+    }
 }
 
 struct Shared<T> {
     inner: Mutex<Inner<T>>,
     have_item: Condvar,
+    have_space: Condvar,
+}
+
+impl<T> Shared<T> {
+    // locks the channel for the receiver, its buffer is drained
+    fn lock_receiver(&self) -> MutexGuard<'_, Inner<T>> {
+        let mut inner = self.inner.lock().unwrap();
+        // the buffer items don't take capacity anymore
+        if std::mem::take(&mut inner.buffered) > 0 {
+            self.notify_space(&mut inner);
+        }
+        inner
+    }
+
+    // an item from the queue, the rest of the queue is swapped into the receiver's buffer
+    fn take(&self, inner: &mut Inner<T>, buffer: &mut VecDeque<T>) -> Option<T> {
+        let v = inner.queue.pop_front()?;
+        inner.received += 1;
+        if !inner.queue.is_empty() {
+            std::mem::swap(&mut inner.queue, buffer);
+        }
+        if inner.capacity.is_some() {
+            inner.buffered = buffer.len();
+            self.notify_space(inner);
+        }
+        Some(v)
+    }
+
+    fn notify_space(&self, inner: &mut Inner<T>) {
+        self.have_space.notify_all();
+        for waker in inner.send_wakers.drain(..) {
+            waker.wake();
+        }
+    }
 }
 
 fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    new(None)
+}
+
+fn sync_channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    new(Some(capacity))
+}
+
+fn new<T>(capacity: Option<usize>) -> (Sender<T>, Receiver<T>) {
     let inner = Inner {
         queue: VecDeque::new(),
         senders: 1,
+        capacity,
+        buffered: 0,
+        receiver: true,
+        receiving: false,
+        received: 0,
+        selectors: Vec::new(),
+        blocked_senders: 0,
+        recv_waker: None,
+        send_wakers: Vec::new(),
     };
     let shared = Shared {
         inner: Mutex::new(inner),
         have_item: Condvar::new(),
+        have_space: Condvar::new(),
     };
     let shared = Arc::new(shared);
     (
@@ -119,12 +534,156 @@ fn channel<T>() -> (Sender<T>, Receiver<T>) {
         Receiver {
             shared: shared.clone(),
             buffer: VecDeque::new(),
+            stream_pending: false,
         },
     )
 }
 
+// Waits on many receivers, each gets the index of the order it's added in.
+// The selecting thread registers itself on every channel, and senders unpark it.
+pub struct Select<'a> {
+    receivers: Vec<&'a dyn Selectable>,
+    // where the next search starts, so the first receiver doesn't starve the others
+    start: usize,
+}
+
+trait Selectable {
+    fn is_ready(&self) -> bool;
+    fn watch(&self, thread: &Thread);
+    fn unwatch(&self, thread: &Thread);
+}
+
+impl<T> Selectable for Receiver<T> {
+    // Recv won't block: there is an item, all senders are dropped, or a rendezvous sender is
+    // blocked and hands off once recv waits. `Select` doesn't wait like a receiver, so a
+    // rendezvous sender only hands off to the arm which is taken. An async sender waiting on a
+    // rendezvous channel isn't seen, `select` waits for it to be polled again.
+    fn is_ready(&self) -> bool {
+        if !self.buffer.is_empty() {
+            return true;
+        }
+        let inner = self.shared.inner.lock().unwrap();
+        !inner.queue.is_empty()
+            || inner.senders == 0
+            || (inner.capacity == Some(0) && inner.blocked_senders > 0)
+    }
+
+    fn watch(&self, thread: &Thread) {
+        let mut inner = self.shared.inner.lock().unwrap();
+        inner.selectors.push(thread.clone());
+    }
+
+    fn unwatch(&self, thread: &Thread) {
+        let mut inner = self.shared.inner.lock().unwrap();
+        inner.selectors.retain(|t| t.id() != thread.id());
+    }
+}
+
+impl<'a> Select<'a> {
+    fn new() -> Self {
+        Select {
+            receivers: Vec::new(),
+            start: 0,
+        }
+    }
+
+    fn recv<T>(&mut self, receiver: &'a Receiver<T>) -> usize {
+        self.receivers.push(receiver);
+        self.receivers.len() - 1
+    }
+
+    // the index of a ready receiver, `recv` on it won't block
+    fn ready(&mut self) -> usize {
+        self.ready_until(None).unwrap()
+    }
+
+    fn try_ready(&mut self) -> Option<usize> {
+        self.find()
+    }
+
+    fn ready_timeout(&mut self, timeout: Duration) -> Option<usize> {
+        self.ready_until(Some(Instant::now() + timeout))
+    }
+
+    fn ready_until(&mut self, deadline: Option<Instant>) -> Option<usize> {
+        let thread = thread::current();
+        for receiver in &self.receivers {
+            receiver.watch(&thread);
+        }
+        // a send after `watch` unparks the thread, so it can't be missed
+        let index = loop {
+            if let Some(index) = self.find() {
+                break Some(index);
+            }
+            match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(timeout) if timeout > Duration::ZERO => thread::park_timeout(timeout),
+                    _ => break None,
+                },
+                None => thread::park(),
+            }
+        };
+        for receiver in &self.receivers {
+            receiver.unwatch(&thread);
+        }
+        index
+    }
+
+    fn find(&mut self) -> Option<usize> {
+        let len = self.receivers.len();
+        let index = (0..len)
+            .map(|i| (self.start + i) % len)
+            .find(|&i| self.receivers[i].is_ready())?;
+        self.start = (index + 1) % len;
+        Some(index)
+    }
+}
+
+// select! {
+//     recv(rx1) -> v => ..., // v is `rx1.recv()`, None when rx1 is disconnected
+//     recv(rx2) -> v => ...,
+//     timeout(duration) => ..., // or `default => ...` to not block, optional
+// }
+// Like the channels, it's only used in this module.
+#[allow(unused_macros)]
+macro_rules! select {
+    ($(recv($rx:expr) -> $v:pat => $body:expr,)+ timeout($timeout:expr) => $none:expr $(,)?) => {
+        select!(@select ready_timeout($timeout), $none, $(recv($rx) -> $v => $body),+)
+    };
+    ($(recv($rx:expr) -> $v:pat => $body:expr,)+ default => $none:expr $(,)?) => {
+        select!(@select try_ready(), $none, $(recv($rx) -> $v => $body),+)
+    };
+    ($(recv($rx:expr) -> $v:pat => $body:expr),+ $(,)?) => {
+        select!(@select ready(), unreachable!(), $(recv($rx) -> $v => $body),+)
+    };
+    (@select $ready:ident($($arg:expr)?), $none:expr, $(recv($rx:expr) -> $v:pat => $body:expr),+) => {{
+        let index: Option<usize> = {
+            let mut sel = Select::new();
+            $(
+                sel.recv(&$rx);
+            )+
+            sel.$ready($($arg)?).into()
+        };
+        // count down to the selected arm
+        let mut index = index;
+        let mut result = None;
+        $(
+            if index == Some(0) {
+                let $v = $rx.recv();
+                result = Some($body);
+            }
+            index = index.and_then(|i| i.checked_sub(1));
+        )+
+        match result {
+            Some(result) => result,
+            None => $none,
+        }
+    }};
+}
+
 #[cfg(test)]
 mod test {
+    use std::sync::atomic::{AtomicBool, Ordering};
     use std::thread;
     use std::time;
 
@@ -133,16 +692,16 @@ mod test {
     #[test]
     fn works() {
         let (tx, mut rx) = channel();
-        tx.send(1);
-        tx.send(2);
-        tx.send(3);
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        tx.send(3).unwrap();
         assert_eq!(rx.recv(), Some(1));
         assert_eq!(rx.buffer.len(), 2);
         assert_eq!(rx.recv(), Some(2));
         assert_eq!(rx.buffer.len(), 1);
         assert_eq!(rx.recv(), Some(3));
         assert_eq!(rx.buffer.len(), 0);
-        tx.send(4);
+        tx.send(4).unwrap();
         assert_eq!(rx.recv(), Some(4));
         assert_eq!(rx.buffer.len(), 0);
     }
@@ -152,7 +711,7 @@ mod test {
         let (tx, mut rx) = channel();
         thread::spawn(move || {
             thread::sleep(time::Duration::from_millis(5));
-            tx.send(1);
+            tx.send(1).unwrap();
         });
         // if not waiting, recv will likely fail because we tx send after 5ms
         assert_eq!(rx.recv(), Some(1));
@@ -161,18 +720,418 @@ mod test {
     #[test]
     fn tx_close() {
         let (tx, mut rx) = channel();
-        tx.send(1);
-        tx.send(2);
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
         drop(tx);
         assert_eq!(rx.recv(), Some(1));
         assert_eq!(rx.recv(), Some(2));
         assert_eq!(rx.recv(), None);
     }
 
+    #[test]
+    fn sync_send_wait_and_notify() {
+        let (tx, mut rx) = sync_channel(2);
+        let handle = thread::spawn(move || {
+            for n in 1..=3 {
+                tx.send(n).unwrap();
+            }
+            tx
+        });
+        thread::sleep(time::Duration::from_millis(5));
+        // the third send waits for space
+        assert!(!handle.is_finished());
+        assert_eq!(rx.recv(), Some(1));
+        let tx = handle.join().unwrap();
+        assert_eq!(tx.try_send(4), Err(TrySendError::Full(4)));
+        assert_eq!(rx.recv(), Some(2));
+        assert_eq!(rx.recv(), Some(3));
+    }
+
+    #[test]
+    fn sync_capacity_counts_buffer() {
+        let (tx, mut rx) = sync_channel(2);
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        assert_eq!(tx.try_send(3), Err(TrySendError::Full(3)));
+        assert_eq!(rx.recv(), Some(1));
+        assert_eq!(rx.buffer.len(), 1);
+        // 2 is in the receiver's buffer, so there is room for one more
+        assert_eq!(tx.try_send(3), Ok(()));
+        assert_eq!(tx.try_send(4), Err(TrySendError::Full(4)));
+        assert_eq!(rx.recv(), Some(2));
+        assert_eq!(rx.recv(), Some(3));
+        assert_eq!(tx.try_send(4), Ok(()));
+        assert_eq!(tx.try_send(5), Ok(()));
+        assert_eq!(tx.try_send(6), Err(TrySendError::Full(6)));
+    }
+
+    #[test]
+    fn rendezvous_hand_off() {
+        let (tx, mut rx) = sync_channel(0);
+        let sent = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let sent = sent.clone();
+            thread::spawn(move || {
+                tx.send(1).unwrap();
+                sent.store(true, Ordering::SeqCst);
+            })
+        };
+        thread::sleep(time::Duration::from_millis(5));
+        assert!(!sent.load(Ordering::SeqCst));
+        assert_eq!(rx.recv(), Some(1));
+        handle.join().unwrap();
+        assert!(sent.load(Ordering::SeqCst));
+        assert_eq!(rx.recv(), None);
+    }
+
+    #[test]
+    fn rendezvous_many_senders() {
+        let (tx, mut rx) = sync_channel(0);
+        let handles: Vec<_> = (0..4)
+            .map(|n| {
+                let tx = tx.clone();
+                thread::spawn(move || tx.send(n).unwrap())
+            })
+            .collect();
+        drop(tx);
+        let mut received: Vec<_> = (0..4).map(|_| rx.recv().unwrap()).collect();
+        received.sort_unstable();
+        assert_eq!(received, vec![0, 1, 2, 3]);
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(rx.recv(), None);
+    }
+
+    #[test]
+    fn rendezvous_try_send() {
+        let (tx, mut rx) = sync_channel(0);
+        assert_eq!(tx.try_send(1), Err(TrySendError::Full(1)));
+        let handle = thread::spawn(move || rx.recv());
+        thread::sleep(time::Duration::from_millis(5));
+        assert_eq!(tx.try_send(2), Ok(()));
+        assert_eq!(handle.join().unwrap(), Some(2));
+    }
+
+    #[test]
+    fn rendezvous_try_recv() {
+        let (tx, mut rx) = sync_channel(0);
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        let handle = thread::spawn(move || tx.send(1));
+        thread::sleep(time::Duration::from_millis(5));
+        // takes the value of the blocked sender
+        assert_eq!(rx.try_recv(), Ok(1));
+        assert_eq!(handle.join().unwrap(), Ok(()));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn rendezvous_rx_close() {
+        let (tx, rx) = sync_channel(0);
+        let handle = thread::spawn(move || tx.send(1));
+        thread::sleep(time::Duration::from_millis(5));
+        // the parked sender wakes up and gets its value back
+        drop(rx);
+        assert_eq!(handle.join().unwrap(), Err(SendError(1)));
+    }
+
     #[test]
     fn rx_close() {
         let (tx, rx) = channel();
+        assert!(!tx.is_closed());
         drop(rx);
-        tx.send(42);
+        assert!(tx.is_closed());
+        assert_eq!(tx.send(42), Err(SendError(42)));
+        assert_eq!(tx.try_send(43), Err(TrySendError::Disconnected(43)));
+    }
+
+    #[test]
+    fn closed_wait_and_notify() {
+        let (tx, rx) = sync_channel(1);
+        tx.send(1).unwrap();
+        let waiting = tx.clone();
+        let handles = vec![
+            // blocks on the full channel
+            thread::spawn(move || tx.send(2)),
+            thread::spawn(move || {
+                waiting.closed();
+                waiting.send(3)
+            }),
+        ];
+        thread::sleep(time::Duration::from_millis(5));
+        drop(rx);
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(results, vec![Err(SendError(2)), Err(SendError(3))]);
+    }
+
+    #[test]
+    fn try_recv() {
+        let (tx, mut rx) = channel();
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        assert_eq!(rx.try_recv(), Ok(1));
+        drop(tx);
+        // the buffer is drained before the disconnect is seen
+        assert_eq!(rx.try_recv(), Ok(2));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn recv_timeout() {
+        let (tx, mut rx) = channel();
+        let timeout = time::Duration::from_millis(10);
+        let start = Instant::now();
+        assert_eq!(rx.recv_timeout(timeout), Err(RecvTimeoutError::Timeout));
+        assert!(start.elapsed() >= timeout);
+
+        thread::spawn(move || {
+            thread::sleep(time::Duration::from_millis(5));
+            tx.send(1).unwrap();
+        });
+        assert_eq!(rx.recv_timeout(time::Duration::from_secs(1)), Ok(1));
+        assert_eq!(
+            rx.recv_timeout(time::Duration::from_secs(1)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn recv_deadline() {
+        let (tx, mut rx) = sync_channel(0);
+        assert_eq!(
+            rx.recv_deadline(Instant::now()),
+            Err(RecvTimeoutError::Timeout)
+        );
+        // a rendezvous sender hands off to a receiver waiting with a deadline
+        let handle = thread::spawn(move || tx.send(1));
+        let deadline = Instant::now() + time::Duration::from_secs(1);
+        assert_eq!(rx.recv_deadline(deadline), Ok(1));
+        assert_eq!(handle.join().unwrap(), Ok(()));
+    }
+
+    #[test]
+    fn iter() {
+        let (tx, mut rx) = channel();
+        let handle = thread::spawn(move || {
+            for n in 1..=3 {
+                tx.send(n).unwrap();
+                thread::sleep(time::Duration::from_millis(1));
+            }
+        });
+        assert_eq!(rx.iter().collect::<Vec<_>>(), vec![1, 2, 3]);
+        handle.join().unwrap();
+
+        let (tx, rx) = sync_channel(1);
+        thread::spawn(move || {
+            for n in 1..=3 {
+                tx.send(n).unwrap();
+            }
+        });
+        let mut received = Vec::new();
+        for v in rx {
+            received.push(v);
+        }
+        assert_eq!(received, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn try_iter() {
+        let (tx, mut rx) = channel();
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(rx.try_iter().next(), None);
+        tx.send(3).unwrap();
+        assert_eq!((&mut rx).into_iter().next(), Some(3));
+        drop(tx);
+        assert_eq!(rx.try_iter().next(), None);
+    }
+
+    #[test]
+    fn select() {
+        let (tx1, mut rx1) = channel();
+        let (tx2, mut rx2) = sync_channel(0);
+        thread::spawn(move || {
+            thread::sleep(time::Duration::from_millis(5));
+            tx2.send("two").unwrap();
+        });
+        let received = select! {
+            recv(rx1) -> v => v.map(|n: i32| n.to_string()),
+            recv(rx2) -> v => v.map(|s| s.to_string()),
+        };
+        assert_eq!(received, Some("two".to_string()));
+
+        // the rendezvous sender is dropped
+        let received = select! {
+            recv(rx1) -> v => v,
+            recv(rx2) -> v => v.map(|_| 0),
+        };
+        assert_eq!(received, None);
+
+        tx1.send(1).unwrap();
+        let mut sel = Select::new();
+        sel.recv(&rx1);
+        assert_eq!(sel.try_ready(), Some(0));
+        assert_eq!(rx1.recv(), Some(1));
+    }
+
+    #[test]
+    fn select_timeout_and_default() {
+        let (tx1, mut rx1) = channel::<i32>();
+        let (tx2, mut rx2) = channel::<i32>();
+        let timeout = time::Duration::from_millis(10);
+        let start = Instant::now();
+        let received = select! {
+            recv(rx1) -> v => v,
+            recv(rx2) -> v => v,
+            timeout(timeout) => Some(-1),
+        };
+        assert_eq!(received, Some(-1));
+        assert!(start.elapsed() >= timeout);
+
+        let received = select! {
+            recv(rx1) -> v => v,
+            recv(rx2) -> v => v,
+            default => Some(-2),
+        };
+        assert_eq!(received, Some(-2));
+
+        tx2.send(2).unwrap();
+        let received = select! {
+            recv(rx1) -> v => v,
+            recv(rx2) -> v => v,
+            default => Some(-2),
+        };
+        assert_eq!(received, Some(2));
+        drop(tx1);
+    }
+
+    #[test]
+    fn select_is_fair() {
+        let (tx1, mut rx1) = channel();
+        let (tx2, mut rx2) = channel();
+        for n in 0..4 {
+            tx1.send(n).unwrap();
+            tx2.send(n).unwrap();
+        }
+        let mut sel = Select::new();
+        sel.recv(&rx1);
+        sel.recv(&rx2);
+        let picked: Vec<_> = (0..4).map(|_| sel.ready()).collect();
+        assert_eq!(picked, vec![0, 1, 0, 1]);
+        drop(sel);
+        assert_eq!(rx1.recv(), Some(0));
+        assert_eq!(rx2.recv(), Some(0));
+    }
+
+    #[tokio::test]
+    async fn recv_async() {
+        let (tx, mut rx) = channel();
+        // a sync sender wakes the async receiver
+        thread::spawn(move || {
+            thread::sleep(time::Duration::from_millis(5));
+            tx.send(1).unwrap();
+            tx.send(2).unwrap();
+        });
+        assert_eq!(rx.recv_async().await, Some(1));
+        assert_eq!(rx.recv_async().await, Some(2));
+        assert_eq!(rx.recv_async().await, None);
+
+        let (tx, mut rx) = sync_channel(0);
+        let handle = thread::spawn(move || tx.send("hello"));
+        assert_eq!(rx.recv_async().await, Some("hello"));
+        assert_eq!(handle.join().unwrap(), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn send_async() {
+        let (tx, mut rx) = sync_channel(1);
+        tx.send_async(1).await.unwrap();
+        let handle = thread::spawn(move || {
+            thread::sleep(time::Duration::from_millis(5));
+            (rx.recv(), rx.recv())
+        });
+        // waits for the sync receiver to make space
+        tx.send_async(2).await.unwrap();
+        assert_eq!(handle.join().unwrap(), (Some(1), Some(2)));
+        assert_eq!(tx.send_async(3).await, Err(SendError(3)));
+
+        let (tx, mut rx) = sync_channel(0);
+        let handle = tokio::spawn(async move { rx.recv_async().await });
+        tx.send_async(4).await.unwrap();
+        assert_eq!(handle.await.unwrap(), Some(4));
+    }
+
+    #[tokio::test]
+    async fn stream() {
+        let (tx, mut rx) = channel();
+        tokio::spawn(async move {
+            for n in 1..=3 {
+                tx.send_async(n).await.unwrap();
+                tokio::time::sleep(time::Duration::from_millis(1)).await;
+            }
+        });
+        let mut received = Vec::new();
+        while let Some(v) = std::future::poll_fn(|cx| Pin::new(&mut rx).poll_next(cx)).await {
+            received.push(v);
+        }
+        assert_eq!(received, vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn recv_async_cancelled() {
+        let (tx, mut rx) = sync_channel(0);
+        let timeout = time::Duration::from_millis(5);
+        assert!(tokio::time::timeout(timeout, rx.recv_async())
+            .await
+            .is_err());
+        // nobody is waiting after the timeout
+        assert_eq!(tx.try_send(1), Err(TrySendError::Full(1)));
+        assert!(tokio::time::timeout(timeout, tx.send_async(2))
+            .await
+            .is_err());
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[tokio::test]
+    async fn stream_cancelled() {
+        let (tx, mut rx) = sync_channel(0);
+        let timeout = time::Duration::from_millis(5);
+        let next = std::future::poll_fn(|cx| Pin::new(&mut rx).poll_next(cx));
+        assert!(tokio::time::timeout(timeout, next).await.is_err());
+        // the stream is abandoned once the receiver is used another way
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        assert_eq!(tx.try_send(1), Err(TrySendError::Full(1)));
+        assert!(tokio::time::timeout(timeout, tx.send_async(2))
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn select_rendezvous() {
+        let (tx1, mut rx1) = sync_channel(0);
+        let (tx2, mut rx2) = sync_channel(0);
+        let handles = vec![
+            thread::spawn(move || tx1.send(1)),
+            thread::spawn(move || tx2.send(2)),
+        ];
+        thread::sleep(time::Duration::from_millis(5));
+        let received = select! {
+            recv(rx1) -> v => v,
+            recv(rx2) -> v => v,
+        };
+        // only the sender of the taken arm hands off, the other one is still blocked
+        thread::sleep(time::Duration::from_millis(5));
+        let blocked = if received == Some(1) { 1 } else { 0 };
+        assert!(handles[1 - blocked].is_finished());
+        assert!(!handles[blocked].is_finished());
+
+        // the other sender hands off on try_recv
+        let rx = if blocked == 0 { &mut rx1 } else { &mut rx2 };
+        assert_eq!(rx.try_recv(), Ok(blocked as i32 + 1));
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), Ok(()));
+        }
     }
 }
@@ -0,0 +1,273 @@
+// A single use channel, like tokio::sync::oneshot
+// `send` takes the sender by value, so at most one value is sent. Dropping either side is seen
+// by the other: the receiver gets `RecvError`, and the sender gets its value back or is woken
+// up in `closed`.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+// the sender is dropped without sending
+#[derive(Debug, PartialEq)]
+pub struct RecvError;
+
+#[derive(Debug, PartialEq)]
+pub enum TryRecvError {
+    Empty,
+    Closed,
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    // the value is given back when the receiver is dropped
+    pub fn send(self, v: T) -> Result<(), T> {
+        let mut inner = self.shared.inner.lock().unwrap();
+        if !inner.receiver {
+            return Err(v);
+        }
+        inner.value = Some(v);
+        // `Drop` notifies the receiver
+        drop(inner);
+        Ok(())
+    }
+
+    // the receiver is dropped, so the value isn't needed anymore
+    pub fn is_closed(&self) -> bool {
+        !self.shared.inner.lock().unwrap().receiver
+    }
+
+    // block until the receiver is dropped
+    pub fn blocking_closed(&self) {
+        let mut inner = self.shared.inner.lock().unwrap();
+        while inner.receiver {
+            inner = self.shared.done.wait(inner).unwrap();
+        }
+    }
+
+    // completes when the receiver is dropped, to stop working on a value nobody waits for
+    pub fn closed(&mut self) -> Closed<'_, T> {
+        Closed { sender: self }
+    }
+}
+
+// future of `Sender::closed`
+pub struct Closed<'a, T> {
+    sender: &'a mut Sender<T>,
+}
+
+impl<T> Future for Closed<'_, T> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut inner = self.sender.shared.inner.lock().unwrap();
+        if !inner.receiver {
+            return Poll::Ready(());
+        }
+        inner.sender_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut inner = self.shared.inner.lock().unwrap();
+        inner.sender = false;
+        let waker = inner.waker.take();
+        drop(inner);
+
+        self.shared.done.notify_all();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let mut inner = self.shared.inner.lock().unwrap();
+        inner.take()
+    }
+
+    pub fn blocking_recv(self) -> Result<T, RecvError> {
+        let mut inner = self.shared.inner.lock().unwrap();
+        loop {
+            match inner.take() {
+                Ok(v) => return Ok(v),
+                Err(TryRecvError::Closed) => return Err(RecvError),
+                Err(TryRecvError::Empty) => {
+                    inner = self.shared.done.wait(inner).unwrap();
+                }
+            }
+        }
+    }
+}
+
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut inner = self.shared.inner.lock().unwrap();
+        match inner.take() {
+            Ok(v) => Poll::Ready(Ok(v)),
+            Err(TryRecvError::Closed) => Poll::Ready(Err(RecvError)),
+            Err(TryRecvError::Empty) => {
+                inner.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+// wakes the sender waiting in `closed`
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut inner = self.shared.inner.lock().unwrap();
+        inner.receiver = false;
+        let waker = inner.sender_waker.take();
+        drop(inner);
+
+        self.shared.done.notify_all();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+struct Inner<T> {
+    value: Option<T>,
+    sender: bool,
+    receiver: bool,
+    // the task awaiting the receiver
+    waker: Option<Waker>,
+    // the task awaiting `Sender::closed`
+    sender_waker: Option<Waker>,
+}
+
+impl<T> Inner<T> {
+    fn take(&mut self) -> Result<T, TryRecvError> {
+        match self.value.take() {
+            Some(v) => Ok(v),
+            None if !self.sender => Err(TryRecvError::Closed),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+}
+
+struct Shared<T> {
+    inner: Mutex<Inner<T>>,
+    done: Condvar,
+}
+
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let inner = Inner {
+        value: None,
+        sender: true,
+        receiver: true,
+        waker: None,
+        sender_waker: None,
+    };
+    let shared = Shared {
+        inner: Mutex::new(inner),
+        done: Condvar::new(),
+    };
+    let shared = Arc::new(shared);
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use std::time;
+
+    use super::*;
+
+    #[test]
+    fn works() {
+        let (tx, mut rx) = channel();
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        tx.send(1).unwrap();
+        assert_eq!(rx.try_recv(), Ok(1));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Closed));
+    }
+
+    #[test]
+    fn recv_wait_and_notify() {
+        let (tx, rx) = channel();
+        thread::spawn(move || {
+            thread::sleep(time::Duration::from_millis(5));
+            tx.send(1).unwrap();
+        });
+        assert_eq!(rx.blocking_recv(), Ok(1));
+    }
+
+    #[tokio::test]
+    async fn recv_async() {
+        let (tx, rx) = channel();
+        thread::spawn(move || {
+            thread::sleep(time::Duration::from_millis(5));
+            tx.send("hello").unwrap();
+        });
+        assert_eq!(rx.await, Ok("hello"));
+
+        let (tx, rx) = channel::<()>();
+        thread::spawn(move || {
+            thread::sleep(time::Duration::from_millis(5));
+            drop(tx);
+        });
+        assert_eq!(rx.await, Err(RecvError));
+    }
+
+    #[test]
+    fn tx_close() {
+        let (tx, rx) = channel::<i32>();
+        drop(tx);
+        assert_eq!(rx.blocking_recv(), Err(RecvError));
+    }
+
+    #[test]
+    fn rx_close() {
+        let (tx, rx) = channel();
+        assert!(!tx.is_closed());
+        drop(rx);
+        assert!(tx.is_closed());
+        assert_eq!(tx.send(42), Err(42));
+    }
+
+    #[test]
+    fn closed_wait_and_notify() {
+        let (tx, rx) = channel::<i32>();
+        let handle = thread::spawn(move || {
+            tx.blocking_closed();
+            tx.is_closed()
+        });
+        thread::sleep(time::Duration::from_millis(5));
+        drop(rx);
+        assert!(handle.join().unwrap());
+    }
+
+    #[tokio::test]
+    async fn closed_async() {
+        let (mut tx, rx) = channel::<i32>();
+        thread::spawn(move || {
+            thread::sleep(time::Duration::from_millis(5));
+            drop(rx);
+        });
+        tx.closed().await;
+        assert_eq!(tx.send(1), Err(1));
+    }
+}
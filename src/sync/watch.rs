@@ -0,0 +1,328 @@
+// A single value channel, like tokio::sync::watch
+// Receivers see only the latest value, every send bumps the version so receivers can tell
+// whether they've seen it.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex, RwLock, RwLockReadGuard},
+    task::{Context, Poll, Waker},
+};
+
+// the value is given back when there is no receiver
+#[derive(Debug, PartialEq)]
+pub struct SendError<T>(pub T);
+
+// the sender is dropped
+#[derive(Debug, PartialEq)]
+pub struct RecvError;
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    pub fn send(&self, v: T) -> Result<(), SendError<T>> {
+        if self.is_closed() {
+            return Err(SendError(v));
+        }
+        self.send_modify(|value| *value = v);
+        Ok(())
+    }
+
+    // modify in place and notify receivers, even when there is no receiver
+    pub fn send_modify<F: FnOnce(&mut T)>(&self, modify: F) {
+        self.send_if_modified(|value| {
+            modify(value);
+            true
+        });
+    }
+
+    // receivers are notified only when `modify` returns true
+    pub fn send_if_modified<F: FnOnce(&mut T) -> bool>(&self, modify: F) -> bool {
+        let mut value = self.shared.value.write().unwrap();
+        if !modify(&mut value) {
+            return false;
+        }
+        // bump the version before releasing the value, so a receiver can't see the new value
+        // with the old version and get notified again for it
+        let mut state = self.shared.state.lock().unwrap();
+        state.version += 1;
+        drop(value);
+        self.shared.notify(state);
+        true
+    }
+
+    pub fn borrow(&self) -> RwLockReadGuard<'_, T> {
+        self.shared.value.read().unwrap()
+    }
+
+    pub fn subscribe(&self) -> Receiver<T> {
+        let mut state = self.shared.state.lock().unwrap();
+        state.receivers += 1;
+        let seen = state.version;
+        drop(state);
+
+        Receiver {
+            shared: self.shared.clone(),
+            seen,
+        }
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.shared.state.lock().unwrap().receivers
+    }
+
+    // all receivers are dropped
+    pub fn is_closed(&self) -> bool {
+        self.receiver_count() == 0
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.closed = true;
+        self.shared.notify(state);
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    // version of the value this receiver has seen
+    seen: u64,
+}
+
+impl<T> Receiver<T> {
+    pub fn borrow(&self) -> RwLockReadGuard<'_, T> {
+        self.shared.value.read().unwrap()
+    }
+
+    // borrow and mark the value as seen
+    pub fn borrow_and_update(&mut self) -> RwLockReadGuard<'_, T> {
+        let value = self.shared.value.read().unwrap();
+        self.seen = self.shared.state.lock().unwrap().version;
+        value
+    }
+
+    pub fn has_changed(&self) -> Result<bool, RecvError> {
+        let state = self.shared.state.lock().unwrap();
+        if state.closed {
+            return Err(RecvError);
+        }
+        Ok(state.version != self.seen)
+    }
+
+    // Wait for a value newer than the seen one, and mark it as seen.
+    // Fails when the sender is dropped, after the last value is seen.
+    pub fn blocking_changed(&mut self) -> Result<(), RecvError> {
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            if state.version != self.seen {
+                self.seen = state.version;
+                return Ok(());
+            }
+            if state.closed {
+                return Err(RecvError);
+            }
+            state = self.shared.changed.wait(state).unwrap();
+        }
+    }
+
+    pub fn changed(&mut self) -> Changed<'_, T> {
+        Changed { receiver: self }
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        let mut state = self.shared.state.lock().unwrap();
+        state.receivers += 1;
+        drop(state);
+
+        Receiver {
+            shared: self.shared.clone(),
+            seen: self.seen,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.receivers -= 1;
+    }
+}
+
+// future of `Receiver::changed`
+pub struct Changed<'a, T> {
+    receiver: &'a mut Receiver<T>,
+}
+
+impl<T> Future for Changed<'_, T> {
+    type Output = Result<(), RecvError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let receiver = &mut self.get_mut().receiver;
+        let mut state = receiver.shared.state.lock().unwrap();
+        if state.version != receiver.seen {
+            receiver.seen = state.version;
+            return Poll::Ready(Ok(()));
+        }
+        if state.closed {
+            return Poll::Ready(Err(RecvError));
+        }
+        // a future may be polled many times before it's woken
+        if !state.wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            state.wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+struct State {
+    version: u64,
+    receivers: usize,
+    closed: bool,
+    // async receivers waiting for a change
+    wakers: Vec<Waker>,
+}
+
+struct Shared<T> {
+    value: RwLock<T>,
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+impl<T> Shared<T> {
+    fn notify(&self, mut state: std::sync::MutexGuard<'_, State>) {
+        let wakers = std::mem::take(&mut state.wakers);
+        drop(state);
+
+        self.changed.notify_all();
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+pub fn channel<T>(init: T) -> (Sender<T>, Receiver<T>) {
+    let state = State {
+        version: 0,
+        receivers: 1,
+        closed: false,
+        wakers: Vec::new(),
+    };
+    let shared = Shared {
+        value: RwLock::new(init),
+        state: Mutex::new(state),
+        changed: Condvar::new(),
+    };
+    let shared = Arc::new(shared);
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared, seen: 0 },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use std::time;
+
+    use super::*;
+
+    #[test]
+    fn works() {
+        let (tx, mut rx) = channel(1);
+        assert_eq!(*rx.borrow(), 1);
+        assert_eq!(rx.has_changed(), Ok(false));
+
+        tx.send(2).unwrap();
+        tx.send(3).unwrap();
+        assert_eq!(rx.has_changed(), Ok(true));
+        // only the latest value is kept
+        assert_eq!(*rx.borrow_and_update(), 3);
+        assert_eq!(rx.has_changed(), Ok(false));
+        assert_eq!(*tx.borrow(), 3);
+    }
+
+    #[test]
+    fn changed_wait_and_notify() {
+        let (tx, mut rx) = channel(0);
+        let handle = thread::spawn(move || {
+            let mut values = Vec::new();
+            while rx.blocking_changed().is_ok() {
+                values.push(*rx.borrow());
+            }
+            values
+        });
+        thread::sleep(time::Duration::from_millis(5));
+        tx.send(1).unwrap();
+        thread::sleep(time::Duration::from_millis(5));
+        tx.send(2).unwrap();
+        thread::sleep(time::Duration::from_millis(5));
+        drop(tx);
+        assert_eq!(handle.join().unwrap(), vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn changed_async() {
+        let (tx, mut rx) = channel("hello");
+        let mut rx2 = rx.clone();
+        let handle = tokio::spawn(async move {
+            rx2.changed().await.unwrap();
+            *rx2.borrow()
+        });
+        tokio::time::sleep(time::Duration::from_millis(5)).await;
+
+        tx.send("world").unwrap();
+        assert_eq!(handle.await.unwrap(), "world");
+        rx.changed().await.unwrap();
+        assert_eq!(*rx.borrow(), "world");
+    }
+
+    #[test]
+    fn send_modify() {
+        let (tx, mut rx) = channel(vec![1]);
+        tx.send_modify(|v| v.push(2));
+        assert_eq!(rx.blocking_changed(), Ok(()));
+        assert_eq!(*rx.borrow(), vec![1, 2]);
+
+        assert!(!tx.send_if_modified(|v| v.len() > 5));
+        assert_eq!(rx.has_changed(), Ok(false));
+        assert!(tx.send_if_modified(|v| {
+            v.clear();
+            true
+        }));
+        assert_eq!(rx.has_changed(), Ok(true));
+    }
+
+    #[test]
+    fn tx_close() {
+        let (tx, mut rx) = channel(1);
+        tx.send(2).unwrap();
+        drop(tx);
+        assert_eq!(rx.has_changed(), Err(RecvError));
+        // the last value is still seen once
+        assert_eq!(rx.blocking_changed(), Ok(()));
+        assert_eq!(*rx.borrow(), 2);
+        assert_eq!(rx.blocking_changed(), Err(RecvError));
+    }
+
+    #[test]
+    fn rx_close() {
+        let (tx, rx) = channel(1);
+        let rx2 = tx.subscribe();
+        assert_eq!(tx.receiver_count(), 2);
+        drop(rx);
+        drop(rx2);
+        assert!(tx.is_closed());
+        assert_eq!(tx.send(2), Err(SendError(2)));
+        // the value can still be modified
+        tx.send_modify(|v| *v = 3);
+        assert_eq!(*tx.borrow(), 3);
+    }
+}
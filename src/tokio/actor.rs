@@ -1,48 +1,374 @@
 // based on https://github.com/tyrchen/rust-training/blob/3014340a0f6da8d60e6a2f5912a5ae1af466c830/live_coding/training_code/src/actor.rs
-use anyhow::Result;
-use tokio::sync::{mpsc, mpsc::Receiver, oneshot};
+use std::{
+    any::type_name,
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
 
-pub trait Actor {
+use anyhow::{anyhow, Result};
+use tokio::sync::{
+    mpsc,
+    mpsc::{error::TrySendError, Receiver},
+    oneshot,
+};
+use tokio::time::Instant;
+use tracing::{info_span, Span};
+
+use super::rate_limit::{Pacer, RateLimiter};
+
+pub trait Actor: Sized {
     type Request;
     type Reply;
-    fn handle_call(&mut self, msg: Self::Request) -> Result<Self::Reply>;
+    // requests stashed over this limit get an error reply
+    const STASH_CAPACITY: usize = 64;
+    fn handle_call(&mut self, msg: Self::Request, ctx: &mut Context<Self>) -> Result<Self::Reply>;
+}
+
+// A behaviour replaces `handle_call` for subsequent messages, see `Context::become_behaviour`.
+pub trait Behaviour<A: Actor>: Send {
+    fn handle_call(
+        &mut self,
+        actor: &mut A,
+        msg: A::Request,
+        ctx: &mut Context<A>,
+    ) -> Result<A::Reply>;
+}
+
+impl<A, F> Behaviour<A> for F
+where
+    A: Actor,
+    F: FnMut(&mut A, A::Request, &mut Context<A>) -> Result<A::Reply> + Send,
+{
+    fn handle_call(
+        &mut self,
+        actor: &mut A,
+        msg: A::Request,
+        ctx: &mut Context<A>,
+    ) -> Result<A::Reply> {
+        self(actor, msg, ctx)
+    }
+}
+
+// Replies of recently handled requests by id, duplicates within the window get the cached reply.
+// Only successful replies are cached, so a failed request can be retried.
+struct Replies<Reply> {
+    window: Duration,
+    cache: HashMap<u64, Reply>,
+    // ids in the order they're cached, for expiry
+    order: VecDeque<(Instant, u64)>,
+    // duplicates of a stashed request, replied together with it
+    waiting: HashMap<u64, Vec<oneshot::Sender<Result<Reply>>>>,
+    // `Context` can't require `Reply: Clone`, so `spawn_idempotent` passes `Clone::clone`
+    clone: fn(&Reply) -> Reply,
+}
+
+impl<Reply> Replies<Reply> {
+    fn expire(&mut self) {
+        while let Some(&(at, id)) = self.order.front() {
+            if at.elapsed() < self.window {
+                break;
+            }
+            self.order.pop_front();
+            self.cache.remove(&id);
+        }
+    }
+
+    fn reply(&mut self, id: u64, reply: &Result<Reply>) {
+        if let Ok(reply) = reply {
+            self.cache.insert(id, (self.clone)(reply));
+            self.order.push_back((Instant::now(), id));
+        }
+        for sender in self.waiting.remove(&id).unwrap_or_default() {
+            let _ = sender.send(match reply {
+                Ok(reply) => Ok((self.clone)(reply)),
+                Err(e) => Err(clone_error(e)),
+            });
+        }
+    }
+}
+
+// `anyhow::Error` isn't Clone, rebuild it from the messages of its chain to keep the causes
+fn clone_error(e: &anyhow::Error) -> anyhow::Error {
+    let mut messages: Vec<String> = e.chain().map(|cause| cause.to_string()).collect();
+    let mut err = anyhow!(messages.pop().unwrap_or_default());
+    while let Some(message) = messages.pop() {
+        err = err.context(message);
+    }
+    err
+}
+
+enum Change<A> {
+    Become(Box<dyn Behaviour<A>>),
+    Unbecome,
+}
+
+pub struct Context<A: Actor> {
+    behaviours: Vec<Box<dyn Behaviour<A>>>,
+    // applied after the current message is handled
+    changes: Vec<Change<A>>,
+    stashed: Option<A::Request>,
+    stash: VecDeque<ActorMessage<A::Request, A::Reply>>,
+    unstash: bool,
+    // unstashed requests, they're handled before the mailbox
+    unstashed: VecDeque<ActorMessage<A::Request, A::Reply>>,
+    replies: Option<Replies<A::Reply>>,
+}
+
+impl<A: Actor> Context<A> {
+    fn new(replies: Option<Replies<A::Reply>>) -> Self {
+        Context {
+            behaviours: Vec::new(),
+            changes: Vec::new(),
+            stashed: None,
+            stash: VecDeque::new(),
+            unstash: false,
+            unstashed: VecDeque::new(),
+            replies,
+        }
+    }
+
+    // `become` is a reserved keyword
+    pub fn become_behaviour<B: Behaviour<A> + 'static>(&mut self, behaviour: B) {
+        self.changes.push(Change::Become(Box::new(behaviour)));
+    }
+
+    // go back to the previous behaviour, or `Actor::handle_call` when the stack is empty
+    pub fn unbecome(&mut self) {
+        self.changes.push(Change::Unbecome);
+    }
+
+    // Defer the current request, the caller keeps waiting until it's unstashed and handled.
+    // The handler should return the result: `return ctx.stash(req);`
+    pub fn stash(&mut self, msg: A::Request) -> Result<A::Reply> {
+        self.stashed = Some(msg);
+        Err(anyhow!("request is stashed"))
+    }
+
+    // put all stashed requests back to the front of the mailbox, in the order they were stashed
+    pub fn unstash_all(&mut self) {
+        self.unstash = true;
+    }
+
+    pub fn stash_len(&self) -> usize {
+        self.stash.len()
+    }
+
+    fn handle(&mut self, actor: &mut A, msg: ActorMessage<A::Request, A::Reply>) {
+        let span = info_span!(parent: &msg.span, "handle_call", actor = type_name::<A>());
+        let _enter = span.enter();
+
+        if let (Some(id), Some(replies)) = (msg.id, &mut self.replies) {
+            replies.expire();
+            if let Some(reply) = replies.cache.get(&id) {
+                let _ = msg.sender.send(Ok((replies.clone)(reply)));
+                return;
+            }
+            if self.stash.iter().any(|m| m.id == Some(id)) {
+                replies.waiting.entry(id).or_default().push(msg.sender);
+                return;
+            }
+        }
+
+        let reply = self.handle_call(actor, msg.data);
+        match self.stashed.take() {
+            Some(data) if self.stash.len() < A::STASH_CAPACITY => {
+                self.stash.push_back(ActorMessage {
+                    data,
+                    sender: msg.sender,
+                    span: msg.span,
+                    id: msg.id,
+                });
+            }
+            Some(_) => {
+                let err = Err(anyhow!("stash is full"));
+                // duplicates waiting for this request get the error too
+                if let (Some(id), Some(replies)) = (msg.id, &mut self.replies) {
+                    replies.reply(id, &err);
+                }
+                let _ = msg.sender.send(err);
+            }
+            None => {
+                if let (Some(id), Some(replies)) = (msg.id, &mut self.replies) {
+                    replies.reply(id, &reply);
+                }
+                let _ = msg.sender.send(reply);
+            }
+        }
+
+        if self.unstash {
+            self.unstash = false;
+            while let Some(msg) = self.stash.pop_back() {
+                self.unstashed.push_front(msg);
+            }
+        }
+    }
+
+    fn handle_call(&mut self, actor: &mut A, msg: A::Request) -> Result<A::Reply> {
+        let reply = match self.behaviours.pop() {
+            Some(mut behaviour) => {
+                let reply = behaviour.handle_call(actor, msg, self);
+                self.behaviours.push(behaviour);
+                reply
+            }
+            None => actor.handle_call(msg, self),
+        };
+
+        for change in std::mem::take(&mut self.changes) {
+            match change {
+                Change::Become(behaviour) => self.behaviours.push(behaviour),
+                Change::Unbecome => {
+                    self.behaviours.pop();
+                }
+            }
+        }
+        reply
+    }
 }
 
 pub struct ActorMessage<Request, Reply> {
-    data: Request,
-    sender: oneshot::Sender<Reply>,
+    pub(super) data: Request,
+    pub(super) sender: oneshot::Sender<Result<Reply>>,
+    // span of the caller, the actor handles the message in a child span of it
+    pub(super) span: Span,
+    // set by `Pid::send_with_id`, for deduplication
+    pub(super) id: Option<u64>,
 }
 
-pub fn spawn<A: Actor>(mut actor: A, mailbox: usize) -> Pid<A::Request, A::Reply>
+pub fn spawn<A: Actor>(actor: A, mailbox: usize) -> Pid<A::Request, A::Reply>
 where
     A::Request: Send,
     A::Reply: Send,
     A: Send + 'static,
+{
+    run(actor, mailbox, Context::new(None), None)
+}
+
+// like `spawn`, but requests sent with the same id within `window` are handled only once
+pub fn spawn_idempotent<A>(actor: A, mailbox: usize, window: Duration) -> Pid<A::Request, A::Reply>
+where
+    A::Request: Send,
+    A::Reply: Send + Clone,
+    A: Actor + Send + 'static,
+{
+    let replies = Replies {
+        window,
+        cache: HashMap::new(),
+        order: VecDeque::new(),
+        waiting: HashMap::new(),
+        clone: A::Reply::clone,
+    };
+    run(actor, mailbox, Context::new(Some(replies)), None)
+}
+
+// Like `spawn`, but the actor handles requests at the pace of the limiter. Fails if the limiter
+// is a leaky bucket used by another actor.
+pub fn spawn_rate_limited<A>(
+    actor: A,
+    mailbox: usize,
+    limiter: RateLimiter,
+) -> Result<Pid<A::Request, A::Reply>>
+where
+    A::Request: Send,
+    A::Reply: Send,
+    A: Actor + Send + 'static,
+{
+    let pacer = Pacer::new(limiter)?;
+    Ok(run(actor, mailbox, Context::new(None), Some(pacer)))
+}
+
+fn run<A>(
+    mut actor: A,
+    mailbox: usize,
+    mut ctx: Context<A>,
+    mut pacer: Option<Pacer<A::Request, A::Reply>>,
+) -> Pid<A::Request, A::Reply>
+where
+    A::Request: Send,
+    A::Reply: Send,
+    A: Actor + Send + 'static,
 {
     // compiler needs this explicit type
     let (sender, mut receiver): (_, Receiver<ActorMessage<A::Request, A::Reply>>) =
         mpsc::channel(mailbox);
     tokio::spawn(async move {
-        while let Some(msg) = receiver.recv().await {
-            let reply = actor.handle_call(msg.data).unwrap();
-            let _ = msg.sender.send(reply);
+        loop {
+            // unstashed requests were paced when they came from the mailbox
+            let msg = match ctx.unstashed.pop_front() {
+                Some(msg) => Some(msg),
+                None => match pacer.as_mut() {
+                    Some(pacer) => pacer.recv(&mut receiver).await,
+                    None => receiver.recv().await,
+                },
+            };
+            let msg = match msg {
+                Some(msg) => msg,
+                None => break,
+            };
+            ctx.handle(&mut actor, msg);
         }
     });
 
     Pid { sender }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct Pid<Request, Reply> {
-    sender: mpsc::Sender<ActorMessage<Request, Reply>>,
+    pub(super) sender: mpsc::Sender<ActorMessage<Request, Reply>>,
+}
+
+// derive(Clone) would require Request and Reply to be Clone
+impl<Request, Reply> Clone for Pid<Request, Reply> {
+    fn clone(&self) -> Self {
+        Pid {
+            sender: self.sender.clone(),
+        }
+    }
 }
 
 impl<Request, Reply> Pid<Request, Reply> {
     pub async fn send(&self, data: Request) -> Result<Reply> {
+        self.call(None, data).await
+    }
+
+    // retries should reuse the id, so an actor from `spawn_idempotent` handles the request once
+    pub async fn send_with_id(&self, id: u64, data: Request) -> Result<Reply> {
+        self.call(Some(id), data).await
+    }
+
+    async fn call(&self, id: Option<u64>, data: Request) -> Result<Reply> {
         let (sender, receiver) = oneshot::channel();
-        let msg = ActorMessage { sender, data };
+        let msg = ActorMessage {
+            sender,
+            data,
+            span: Span::current(),
+            id,
+        };
         let _ = self.sender.send(msg).await;
-        Ok(receiver.await?)
+        receiver.await?
+    }
+
+    // fire and forget, fails when the mailbox is full or the actor is gone
+    pub fn tell(&self, data: Request) -> Result<()> {
+        let (sender, _) = oneshot::channel();
+        let msg = ActorMessage {
+            sender,
+            data,
+            span: Span::current(),
+            id: None,
+        };
+        self.sender.try_send(msg).map_err(|e| match e {
+            TrySendError::Full(_) => anyhow!("mailbox is full"),
+            TrySendError::Closed(_) => anyhow!("actor is closed"),
+        })
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.sender.is_closed()
+    }
+
+    // resolves once the actor is gone
+    pub async fn closed(&self) {
+        self.sender.closed().await
     }
 }
 
@@ -58,7 +384,11 @@ mod tests {
         type Request = usize;
         type Reply = usize;
 
-        fn handle_call(&mut self, req: Self::Request) -> Result<Self::Reply> {
+        fn handle_call(
+            &mut self,
+            req: Self::Request,
+            _ctx: &mut Context<Self>,
+        ) -> Result<Self::Reply> {
             self.state += 1;
             println!("state: {}", self.state);
             Ok(req + 1)
@@ -76,4 +406,389 @@ mod tests {
         let result = pid1.send(100).await.unwrap();
         assert_eq!(result, 101);
     }
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Request {
+        Hello(&'static str),
+        Auth(&'static str),
+        Get,
+        Logout,
+    }
+
+    struct Session {
+        user: Option<&'static str>,
+    }
+
+    fn authenticated(
+        actor: &mut Session,
+        req: Request,
+        ctx: &mut Context<Session>,
+    ) -> Result<String> {
+        match req {
+            Request::Get => Ok(format!("hello {}", actor.user.unwrap())),
+            Request::Logout => {
+                actor.user = None;
+                ctx.unbecome();
+                Ok("bye".to_string())
+            }
+            _ => Ok("already authenticated".to_string()),
+        }
+    }
+
+    fn handshaking(
+        actor: &mut Session,
+        req: Request,
+        ctx: &mut Context<Session>,
+    ) -> Result<String> {
+        match req {
+            Request::Auth(user) => {
+                actor.user = Some(user);
+                ctx.become_behaviour(authenticated);
+                Ok("authenticated".to_string())
+            }
+            Request::Logout => {
+                ctx.unbecome();
+                Ok("bye".to_string())
+            }
+            _ => Ok("auth first".to_string()),
+        }
+    }
+
+    impl Actor for Session {
+        type Request = Request;
+        type Reply = String;
+
+        fn handle_call(
+            &mut self,
+            req: Self::Request,
+            ctx: &mut Context<Self>,
+        ) -> Result<Self::Reply> {
+            match req {
+                Request::Hello(version) => {
+                    ctx.become_behaviour(handshaking);
+                    Ok(format!("hello {}", version))
+                }
+                _ => Ok("hello first".to_string()),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn become_and_unbecome() {
+        let pid = spawn(Session { user: None }, 20);
+        assert_eq!(pid.send(Request::Get).await.unwrap(), "hello first");
+        assert_eq!(pid.send(Request::Hello("v1")).await.unwrap(), "hello v1");
+        assert_eq!(pid.send(Request::Get).await.unwrap(), "auth first");
+        assert_eq!(
+            pid.send(Request::Auth("tony")).await.unwrap(),
+            "authenticated"
+        );
+        assert_eq!(pid.send(Request::Get).await.unwrap(), "hello tony");
+
+        // back to handshaking, then to `handle_call`
+        assert_eq!(pid.send(Request::Logout).await.unwrap(), "bye");
+        assert_eq!(pid.send(Request::Get).await.unwrap(), "auth first");
+        assert_eq!(pid.send(Request::Logout).await.unwrap(), "bye");
+        assert_eq!(pid.send(Request::Get).await.unwrap(), "hello first");
+    }
+
+    struct Idle;
+
+    impl Actor for Idle {
+        type Request = &'static str;
+        type Reply = usize;
+
+        fn handle_call(
+            &mut self,
+            req: Self::Request,
+            ctx: &mut Context<Self>,
+        ) -> Result<Self::Reply> {
+            if req == "start" {
+                ctx.become_behaviour(Counter(0));
+            }
+            Ok(0)
+        }
+    }
+
+    struct Counter(usize);
+
+    impl Behaviour<Idle> for Counter {
+        fn handle_call(
+            &mut self,
+            _actor: &mut Idle,
+            req: &'static str,
+            ctx: &mut Context<Idle>,
+        ) -> Result<usize> {
+            if req == "stop" {
+                ctx.unbecome();
+            }
+            self.0 += 1;
+            Ok(self.0)
+        }
+    }
+
+    #[tokio::test]
+    async fn behaviour_object_keeps_state() {
+        let pid = spawn(Idle, 20);
+        assert_eq!(pid.send("tick").await.unwrap(), 0);
+        assert_eq!(pid.send("start").await.unwrap(), 0);
+        assert_eq!(pid.send("tick").await.unwrap(), 1);
+        assert_eq!(pid.send("tick").await.unwrap(), 2);
+        assert_eq!(pid.send("stop").await.unwrap(), 3);
+        assert_eq!(pid.send("tick").await.unwrap(), 0);
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Db {
+        Init,
+        Query(usize),
+    }
+
+    struct Lazy {
+        ready: bool,
+    }
+
+    impl Actor for Lazy {
+        type Request = Db;
+        type Reply = usize;
+        const STASH_CAPACITY: usize = 2;
+
+        fn handle_call(
+            &mut self,
+            req: Self::Request,
+            ctx: &mut Context<Self>,
+        ) -> Result<Self::Reply> {
+            match req {
+                Db::Init => {
+                    self.ready = true;
+                    ctx.unstash_all();
+                    Ok(ctx.stash_len())
+                }
+                Db::Query(_) if !self.ready => ctx.stash(req),
+                Db::Query(n) => Ok(n * 10),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn stash_and_unstash() {
+        let pid = spawn(Lazy { ready: false }, 20);
+        let mut queries = Vec::new();
+        for n in 1..=3 {
+            let pid = pid.clone();
+            queries.push(tokio::spawn(async move { pid.send(Db::Query(n)).await }));
+            tokio::task::yield_now().await;
+        }
+        // make sure all queries are in the mailbox before init
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        assert_eq!(pid.send(Db::Init).await.unwrap(), 2);
+        assert_eq!(queries.remove(0).await.unwrap().unwrap(), 10);
+        assert_eq!(queries.remove(0).await.unwrap().unwrap(), 20);
+        let err = queries.remove(0).await.unwrap().unwrap_err();
+        assert_eq!(err.to_string(), "stash is full");
+        assert_eq!(pid.send(Db::Query(4)).await.unwrap(), 40);
+    }
+
+    #[tokio::test]
+    async fn handler_error_is_replied() {
+        struct Failing;
+
+        impl Actor for Failing {
+            type Request = ();
+            type Reply = ();
+
+            fn handle_call(&mut self, _req: (), _ctx: &mut Context<Self>) -> Result<()> {
+                Err(anyhow!("boom"))
+            }
+        }
+
+        let pid = spawn(Failing, 1);
+        assert_eq!(pid.send(()).await.unwrap_err().to_string(), "boom");
+        assert_eq!(pid.send(()).await.unwrap_err().to_string(), "boom");
+    }
+
+    #[tokio::test]
+    async fn duplicates_get_cached_reply() {
+        let pid = spawn_idempotent(MyActor { state: 0 }, 20, Duration::from_millis(20));
+        assert_eq!(pid.send_with_id(1, 10).await.unwrap(), 11);
+        // the duplicate isn't handled, so its payload doesn't matter
+        assert_eq!(pid.send_with_id(1, 20).await.unwrap(), 11);
+        assert_eq!(pid.send_with_id(2, 20).await.unwrap(), 21);
+        assert_eq!(pid.send(30).await.unwrap(), 31);
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(pid.send_with_id(1, 40).await.unwrap(), 41);
+    }
+
+    #[tokio::test]
+    async fn duplicate_of_stashed_request() {
+        let pid = spawn_idempotent(Lazy { ready: false }, 20, Duration::from_secs(1));
+        let first = {
+            let pid = pid.clone();
+            tokio::spawn(async move { pid.send_with_id(7, Db::Query(1)).await })
+        };
+        let duplicate = {
+            let pid = pid.clone();
+            tokio::spawn(async move { pid.send_with_id(7, Db::Query(1)).await })
+        };
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        // the duplicate waits for the stashed request instead of being stashed too
+        assert_eq!(pid.send(Db::Init).await.unwrap(), 1);
+        assert_eq!(first.await.unwrap().unwrap(), 10);
+        assert_eq!(duplicate.await.unwrap().unwrap(), 10);
+    }
+
+    #[tokio::test]
+    async fn duplicate_of_stashed_request_gets_error_causes() {
+        struct Gate(bool);
+
+        impl Actor for Gate {
+            type Request = bool;
+            type Reply = ();
+
+            fn handle_call(&mut self, open: bool, ctx: &mut Context<Self>) -> Result<()> {
+                if open {
+                    self.0 = true;
+                    ctx.unstash_all();
+                    return Ok(());
+                }
+                if !self.0 {
+                    return ctx.stash(open);
+                }
+                Err(anyhow!("disk is gone")).map_err(|e| e.context("query failed"))
+            }
+        }
+
+        let pid = spawn_idempotent(Gate(false), 20, Duration::from_secs(1));
+        let first = {
+            let pid = pid.clone();
+            tokio::spawn(async move { pid.send_with_id(7, false).await })
+        };
+        let duplicate = {
+            let pid = pid.clone();
+            tokio::spawn(async move { pid.send_with_id(7, false).await })
+        };
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        pid.send(true).await.unwrap();
+        let err = first.await.unwrap().unwrap_err();
+        assert_eq!(format!("{:#}", err), "query failed: disk is gone");
+        let err = duplicate.await.unwrap().unwrap_err();
+        assert_eq!(format!("{:#}", err), "query failed: disk is gone");
+    }
+
+    #[tokio::test]
+    async fn errors_are_not_cached() {
+        struct Flaky(usize);
+
+        impl Actor for Flaky {
+            type Request = ();
+            type Reply = usize;
+
+            fn handle_call(&mut self, _req: (), _ctx: &mut Context<Self>) -> Result<usize> {
+                self.0 += 1;
+                if self.0 == 1 {
+                    return Err(anyhow!("boom"));
+                }
+                Ok(self.0)
+            }
+        }
+
+        let pid = spawn_idempotent(Flaky(0), 1, Duration::from_secs(1));
+        assert!(pid.send_with_id(1, ()).await.is_err());
+        assert_eq!(pid.send_with_id(1, ()).await.unwrap(), 2);
+        assert_eq!(pid.send_with_id(1, ()).await.unwrap(), 2);
+    }
+
+    mod tracing_test {
+        use std::sync::{Arc, Mutex};
+
+        use tracing::{span::Attributes, span::Id, Instrument, Subscriber};
+        use tracing_subscriber::{
+            layer::{self, SubscriberExt},
+            registry::LookupSpan,
+            Layer, Registry,
+        };
+
+        use super::*;
+
+        // span name, id and the index of its parent, ids are reused after spans close
+        type Recorded = (&'static str, Id, Option<usize>);
+
+        #[derive(Clone, Default)]
+        struct Recorder(Arc<Mutex<Vec<Recorded>>>);
+
+        impl<S> Layer<S> for Recorder
+        where
+            S: Subscriber + for<'a> LookupSpan<'a>,
+        {
+            fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: layer::Context<'_, S>) {
+                let mut spans = self.0.lock().unwrap();
+                let parent = ctx.span(id).unwrap().parent().map(|parent| {
+                    spans
+                        .iter()
+                        .rposition(|(_, id, _)| *id == parent.id())
+                        .unwrap()
+                });
+                spans.push((attrs.metadata().name(), id.clone(), parent));
+            }
+        }
+
+        struct Forward {
+            next: Option<Pid<(), ()>>,
+        }
+
+        impl Actor for Forward {
+            type Request = ();
+            type Reply = ();
+
+            fn handle_call(&mut self, _req: (), _ctx: &mut Context<Self>) -> Result<()> {
+                if let Some(next) = &self.next {
+                    next.tell(())?;
+                }
+                Ok(())
+            }
+        }
+
+        #[tokio::test]
+        async fn span_propagates_across_actors() {
+            let recorder = Recorder::default();
+            let subscriber = Registry::default().with(recorder.clone());
+            let _guard = tracing::subscriber::set_default(subscriber);
+
+            let last = spawn(Forward { next: None }, 20);
+            let first = spawn(
+                Forward {
+                    next: Some(last.clone()),
+                },
+                20,
+            );
+            first
+                .send(())
+                .instrument(info_span!("request"))
+                .await
+                .unwrap();
+            // the mailbox is in order, so the forwarded message is handled after this one
+            last.send(()).await.unwrap();
+
+            let spans: Vec<_> = recorder
+                .0
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(name, _, parent)| (*name, *parent))
+                .collect();
+            assert_eq!(
+                spans,
+                vec![
+                    ("request", None),
+                    ("handle_call", Some(0)),
+                    ("handle_call", Some(1)),
+                    ("handle_call", None),
+                ]
+            );
+        }
+    }
 }
@@ -0,0 +1,268 @@
+// Live config, published on a watch channel like in `watch.rs`.
+// The file is polled and reloaded when its content changes. A reload which fails to read, parse or
+// validate keeps the last good config and reports the error on a separate channel.
+// Replace the file atomically (write a temporary file and rename it), or a half written file may
+// be read and reported as an error.
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context, Error, Result};
+use serde::de::DeserializeOwned;
+use tokio::{
+    fs,
+    sync::{mpsc, watch},
+    time,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Toml,
+    Json,
+}
+
+impl Format {
+    fn from_path(path: &Path) -> Result<Self> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Ok(Format::Toml),
+            Some("json") => Ok(Format::Json),
+            _ => Err(anyhow!("unknown config format: {}", path.display())),
+        }
+    }
+
+    fn parse<T: DeserializeOwned>(&self, content: &str) -> Result<T> {
+        Ok(match self {
+            Format::Toml => toml::from_str(content)?,
+            Format::Json => serde_json::from_str(content)?,
+        })
+    }
+}
+
+type Validate<T> = Box<dyn Fn(&T) -> Result<()> + Send + Sync>;
+
+pub struct ConfigWatcher<T> {
+    path: PathBuf,
+    format: Option<Format>,
+    interval: Duration,
+    validate: Validate<T>,
+}
+
+impl<T> ConfigWatcher<T>
+where
+    T: DeserializeOwned + Send + Sync + 'static,
+{
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        ConfigWatcher {
+            path: path.into(),
+            format: None,
+            interval: Duration::from_secs(1),
+            validate: Box::new(|_| Ok(())),
+        }
+    }
+
+    // by default the format comes from the file extension
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn validate<F>(mut self, validate: F) -> Self
+    where
+        F: Fn(&T) -> Result<()> + Send + Sync + 'static,
+    {
+        self.validate = Box::new(validate);
+        self
+    }
+
+    // Fails if the first load fails. Polling stops when all config receivers are dropped.
+    pub async fn spawn(self) -> Result<(watch::Receiver<T>, mpsc::UnboundedReceiver<Error>)> {
+        let format = match self.format {
+            Some(format) => format,
+            None => Format::from_path(&self.path)?,
+        };
+        let content = fs::read_to_string(&self.path)
+            .await
+            .with_context(|| format!("failed to read {}", self.path.display()))?;
+        let config = self.load(format, &content)?;
+
+        let (tx, rx) = watch::channel(config);
+        let (errors, errors_rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            // content of the last read, so a broken file is reported once and an unchanged one
+            // isn't published again
+            let mut last = content;
+            // so a file which can't be read is reported once
+            let mut unreadable = false;
+            loop {
+                tokio::select! {
+                    _ = time::sleep(self.interval) => {}
+                    _ = tx.closed() => break,
+                }
+
+                let content = match fs::read_to_string(&self.path).await {
+                    Ok(content) => content,
+                    Err(e) => {
+                        if !unreadable {
+                            unreadable = true;
+                            let e = anyhow!(e)
+                                .context(format!("failed to read {}", self.path.display()));
+                            let _ = errors.send(e);
+                        }
+                        continue;
+                    }
+                };
+                unreadable = false;
+                if last == content {
+                    continue;
+                }
+
+                let result = self.load(format, &content);
+                last = content;
+                match result {
+                    Ok(config) => {
+                        if tx.send(config).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        let _ = errors.send(e);
+                    }
+                }
+            }
+        });
+        Ok((rx, errors_rx))
+    }
+
+    fn load(&self, format: Format, content: &str) -> Result<T> {
+        let config = format
+            .parse(content)
+            .with_context(|| format!("failed to parse {}", self.path.display()))?;
+        if let Err(e) = (self.validate)(&config) {
+            return Err(e.context(format!("invalid config {}", self.path.display())));
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::bail;
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Config {
+        name: String,
+        workers: usize,
+    }
+
+    fn path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("config-{}-{}", std::process::id(), name))
+    }
+
+    fn write(path: &Path, content: &str) {
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, content).unwrap();
+        std::fs::rename(&tmp, path).unwrap();
+    }
+
+    fn watcher(path: &Path) -> ConfigWatcher<Config> {
+        ConfigWatcher::new(path)
+            .interval(Duration::from_millis(5))
+            .validate(|config: &Config| match config.workers {
+                0 => bail!("workers must be positive"),
+                _ => Ok(()),
+            })
+    }
+
+    fn config(name: &str, workers: usize) -> Config {
+        Config {
+            name: name.to_string(),
+            workers,
+        }
+    }
+
+    #[tokio::test]
+    async fn reload_json() {
+        let path = path("reload.json");
+        write(&path, r#"{"name": "a", "workers": 1}"#);
+        let (mut rx, mut errors) = watcher(&path).spawn().await.unwrap();
+        assert_eq!(*rx.borrow(), config("a", 1));
+
+        write(&path, r#"{"name": "b", "workers": 2}"#);
+        rx.changed().await.unwrap();
+        assert_eq!(*rx.borrow(), config("b", 2));
+
+        // the last good config is kept
+        write(&path, r#"{"name": "c", "#);
+        let err = errors.recv().await.unwrap();
+        assert!(err.to_string().starts_with("failed to parse"));
+        write(&path, r#"{"name": "c", "workers": 0}"#);
+        let err = errors.recv().await.unwrap();
+        assert_eq!(
+            err.to_string(),
+            format!("invalid config {}", path.display())
+        );
+        assert_eq!(err.root_cause().to_string(), "workers must be positive");
+        assert_eq!(*rx.borrow(), config("b", 2));
+
+        write(&path, r#"{"name": "d", "workers": 4}"#);
+        rx.changed().await.unwrap();
+        assert_eq!(*rx.borrow(), config("d", 4));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reload_toml() {
+        let path = path("reload.toml");
+        write(&path, "name = \"a\"\nworkers = 1\n");
+        let (mut rx, mut errors) = watcher(&path).spawn().await.unwrap();
+        assert_eq!(*rx.borrow(), config("a", 1));
+
+        std::fs::remove_file(&path).unwrap();
+        let err = errors.recv().await.unwrap();
+        assert!(err.to_string().starts_with("failed to read"));
+
+        // the same content again isn't published
+        write(&path, "name = \"a\"\nworkers = 1\n");
+        let changed = time::timeout(Duration::from_millis(50), rx.changed());
+        assert!(changed.await.is_err());
+
+        write(&path, "name = \"b\"\nworkers = 2\n");
+        rx.changed().await.unwrap();
+        assert_eq!(*rx.borrow(), config("b", 2));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn first_load_must_succeed() {
+        let path = path("invalid.json");
+        write(&path, r#"{"name": "a", "workers": 0}"#);
+        assert!(watcher(&path).spawn().await.is_err());
+        std::fs::remove_file(&path).unwrap();
+
+        // the file is there, but its format is unknown
+        let path = path.with_extension("yaml");
+        write(&path, "name: a\nworkers: 1\n");
+        let err = watcher(&path).spawn().await.unwrap_err();
+        assert!(err.to_string().starts_with("unknown config format"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn format_overrides_extension() {
+        let path = path("override.conf");
+        write(&path, "name = \"a\"\nworkers = 1\n");
+        assert!(watcher(&path).spawn().await.is_err());
+        let (rx, _errors) = watcher(&path).format(Format::Toml).spawn().await.unwrap();
+        assert_eq!(*rx.borrow(), config("a", 1));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
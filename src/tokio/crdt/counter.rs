@@ -0,0 +1,102 @@
+use std::collections::BTreeMap;
+
+use super::{Crdt, ReplicaId};
+
+// grow-only counter, every replica increments its own slot
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GCounter {
+    counts: BTreeMap<ReplicaId, u64>,
+}
+
+impl GCounter {
+    // returns the delta to gossip
+    pub fn increment(&mut self, replica: ReplicaId, n: u64) -> Self {
+        let count = self.counts.entry(replica).or_insert(0);
+        *count += n;
+        let mut delta = GCounter::default();
+        delta.counts.insert(replica, *count);
+        delta
+    }
+}
+
+impl Crdt for GCounter {
+    type Value = u64;
+
+    fn merge(&mut self, other: &Self) {
+        for (&replica, &count) in other.counts.iter() {
+            let current = self.counts.entry(replica).or_insert(0);
+            *current = (*current).max(count);
+        }
+    }
+
+    fn value(&self) -> u64 {
+        self.counts.values().sum()
+    }
+}
+
+// increments and decrements are two grow-only counters
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PNCounter {
+    p: GCounter,
+    n: GCounter,
+}
+
+impl PNCounter {
+    pub fn increment(&mut self, replica: ReplicaId, n: u64) -> Self {
+        PNCounter {
+            p: self.p.increment(replica, n),
+            n: GCounter::default(),
+        }
+    }
+
+    pub fn decrement(&mut self, replica: ReplicaId, n: u64) -> Self {
+        PNCounter {
+            p: GCounter::default(),
+            n: self.n.increment(replica, n),
+        }
+    }
+}
+
+impl Crdt for PNCounter {
+    type Value = i64;
+
+    fn merge(&mut self, other: &Self) {
+        self.p.merge(&other.p);
+        self.n.merge(&other.n);
+    }
+
+    fn value(&self) -> i64 {
+        self.p.value() as i64 - self.n.value() as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn g_counter() {
+        let mut a = GCounter::default();
+        let mut b = GCounter::default();
+        let delta_a = a.increment(1, 2);
+        let delta_b = b.increment(2, 3);
+        a.merge(&delta_b);
+        b.merge(&delta_a);
+        // merging twice changes nothing
+        b.merge(&delta_a);
+        assert_eq!(a.value(), 5);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn pn_counter() {
+        let mut a = PNCounter::default();
+        let mut b = PNCounter::default();
+        let delta_a = a.increment(1, 2);
+        let delta_b = b.decrement(2, 5);
+        a.merge(&delta_b);
+        b.merge(&delta_a);
+        assert_eq!(a.value(), -3);
+        assert_eq!(a, b);
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::BTreeMap;
+
+use super::{
+    set::{ORSet, Tag},
+    Crdt, ReplicaId,
+};
+
+// Keys have observed-remove semantics and values are CRDTs merged per key.
+// Every update's delta is kept under the tag of the key's add, so a remove drops the value
+// contributions it has seen, and a re-added key starts from the concurrent updates only.
+// A replica adds the key once and keeps updating under its tag until it's removed, so the state
+// doesn't grow with updates. An update concurrent with a remove which has seen that tag is
+// removed with it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ORMap<K: Ord, V> {
+    keys: ORSet<K>,
+    values: BTreeMap<K, BTreeMap<Tag, V>>,
+}
+
+impl<K: Ord, V> Default for ORMap<K, V> {
+    fn default() -> Self {
+        ORMap {
+            keys: ORSet::default(),
+            values: BTreeMap::new(),
+        }
+    }
+}
+
+impl<K, V> ORMap<K, V>
+where
+    K: Ord + Clone + Send + 'static,
+    V: Crdt,
+{
+    // `f` updates the value and returns its delta
+    pub fn update<F>(&mut self, replica: ReplicaId, key: K, f: F) -> Self
+    where
+        F: FnOnce(&mut V) -> V,
+    {
+        let mut value = self.get(&key).unwrap_or_default();
+        let value = f(&mut value);
+        // the delta has the add of the tag, for the replicas which haven't seen it
+        let live = self
+            .keys
+            .tags(&key)
+            .into_iter()
+            .find(|tag| tag.0 == replica);
+        let (keys, tag) = match live {
+            Some(tag) => (self.keys.added(key.clone(), tag), tag),
+            None => {
+                let keys = self.keys.add(replica, key.clone());
+                let tag = keys.tags(&key).into_iter().next().expect("added tag");
+                (keys, tag)
+            }
+        };
+
+        let mut delta = ORMap {
+            keys,
+            values: BTreeMap::new(),
+        };
+        delta
+            .values
+            .entry(key.clone())
+            .or_default()
+            .insert(tag, value.clone());
+        self.values
+            .entry(key)
+            .or_default()
+            .entry(tag)
+            .or_default()
+            .merge(&value);
+        delta
+    }
+
+    pub fn remove(&mut self, key: &K) -> Self {
+        let keys = self.keys.remove(key);
+        self.values.remove(key);
+        ORMap {
+            keys,
+            values: BTreeMap::new(),
+        }
+    }
+
+    // the values of the key's adds which aren't removed, merged
+    pub fn get(&self, key: &K) -> Option<V> {
+        let tags = self.keys.tags(key);
+        if tags.is_empty() {
+            return None;
+        }
+        let mut value = V::default();
+        for (_, v) in self
+            .values
+            .get(key)?
+            .iter()
+            .filter(|(tag, _)| tags.contains(tag))
+        {
+            value.merge(v);
+        }
+        Some(value)
+    }
+
+    // drop the values of removed adds
+    fn prune(&mut self, key: &K) {
+        let tags = self.keys.tags(key);
+        if let Some(values) = self.values.get_mut(key) {
+            values.retain(|tag, _| tags.contains(tag));
+            if values.is_empty() {
+                self.values.remove(key);
+            }
+        }
+    }
+}
+
+impl<K, V> Crdt for ORMap<K, V>
+where
+    K: Ord + Clone + Send + 'static,
+    V: Crdt,
+{
+    type Value = BTreeMap<K, V::Value>;
+
+    fn merge(&mut self, other: &Self) {
+        self.keys.merge(&other.keys);
+        for (key, values) in other.values.iter() {
+            let current = self.values.entry(key.clone()).or_default();
+            for (tag, value) in values.iter() {
+                current.entry(*tag).or_default().merge(value);
+            }
+        }
+        let keys: Vec<K> = self.values.keys().cloned().collect();
+        for key in keys.iter() {
+            self.prune(key);
+        }
+    }
+
+    fn value(&self) -> BTreeMap<K, V::Value> {
+        self.values
+            .keys()
+            .filter_map(|key| Some((key.clone(), self.get(key)?.value())))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokio::crdt::counter::GCounter;
+
+    #[test]
+    fn update_and_remove() {
+        let mut a: ORMap<&str, GCounter> = ORMap::default();
+        let mut b = ORMap::default();
+        let delta = a.update(1, "x", |c| c.increment(1, 1));
+        b.merge(&delta);
+        let delta = b.update(2, "x", |c| c.increment(2, 2));
+        a.merge(&delta);
+        assert_eq!(a.get(&"x").map(|c| c.value()), Some(3));
+
+        let delta = a.remove(&"x");
+        b.merge(&delta);
+        assert_eq!(b.get(&"x"), None);
+        assert_eq!(a.value(), b.value());
+    }
+
+    #[test]
+    fn updates_reuse_the_tag() {
+        let mut a: ORMap<&str, GCounter> = ORMap::default();
+        let mut b = ORMap::default();
+        b.merge(&a.update(1, "x", |c| c.increment(1, 1)));
+        let keys = a.keys.clone();
+        for _ in 0..100 {
+            b.merge(&a.update(1, "x", |c| c.increment(1, 1)));
+        }
+        assert_eq!(a.keys, keys);
+        assert_eq!(a.values[&"x"].len(), 1);
+        assert_eq!(b, a);
+        assert_eq!(b.get(&"x").map(|c| c.value()), Some(101));
+    }
+
+    #[test]
+    fn readded_key_starts_fresh() {
+        let mut a: ORMap<&str, GCounter> = ORMap::default();
+        let mut b = ORMap::default();
+        b.merge(&a.update(1, "x", |c| c.increment(1, 5)));
+        b.merge(&a.remove(&"x"));
+        b.merge(&a.update(1, "x", |c| c.increment(1, 1)));
+        assert_eq!(a.get(&"x").map(|c| c.value()), Some(1));
+        assert_eq!(b.get(&"x").map(|c| c.value()), Some(1));
+    }
+
+    #[test]
+    fn concurrent_update_survives_remove() {
+        let mut a: ORMap<&str, GCounter> = ORMap::default();
+        let mut b = ORMap::default();
+        b.merge(&a.update(1, "x", |c| c.increment(1, 5)));
+
+        let removed = a.remove(&"x");
+        let updated = b.update(2, "x", |c| c.increment(2, 2));
+        a.merge(&updated);
+        b.merge(&removed);
+        // only the update the remove hasn't seen is left
+        assert_eq!(a.get(&"x").map(|c| c.value()), Some(2));
+        assert_eq!(a.value(), b.value());
+    }
+}
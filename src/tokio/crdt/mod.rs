@@ -0,0 +1,129 @@
+// Delta-state CRDTs: https://arxiv.org/abs/1603.01529
+// Every update returns a delta, which is a small state merged like a full one.
+mod counter;
+mod map;
+mod register;
+mod replica;
+mod set;
+
+pub type ReplicaId = u64;
+
+pub trait Crdt: Clone + Default + Send + 'static {
+    type Value;
+    // must be commutative, associative and idempotent
+    fn merge(&mut self, other: &Self);
+    fn value(&self) -> Self::Value;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Debug;
+
+    use proptest::prelude::*;
+
+    use super::{
+        counter::{GCounter, PNCounter},
+        map::ORMap,
+        register::LWWRegister,
+        set::ORSet,
+        *,
+    };
+
+    const REPLICAS: usize = 3;
+
+    // Runs each op on its replica, and delivers every delta to every replica in the given
+    // order, which may repeat deltas. After each op a replica merges the deltas of its order
+    // up to the first one not made yet, so ops run on replicas which have seen some of the
+    // deltas only. All replicas must end up with the same value.
+    fn converges<C, O>(
+        ops: Vec<(usize, O)>,
+        orders: Vec<Vec<usize>>,
+        apply: fn(&mut C, ReplicaId, O) -> C,
+    ) where
+        C: Crdt,
+        C::Value: PartialEq + Debug,
+    {
+        let mut replicas = vec![C::default(); REPLICAS];
+        let mut orders: Vec<_> = orders.into_iter().map(|order| order.into_iter()).collect();
+        let mut next: Vec<Option<usize>> = orders.iter_mut().map(|order| order.next()).collect();
+        let mut deltas: Vec<C> = Vec::new();
+        for (replica, op) in ops {
+            deltas.push(apply(&mut replicas[replica], replica as ReplicaId + 1, op));
+            for ((replica, order), next) in replicas.iter_mut().zip(&mut orders).zip(&mut next) {
+                while let Some(i) = next.filter(|&i| i < deltas.len()) {
+                    replica.merge(&deltas[i]);
+                    *next = order.next();
+                }
+            }
+        }
+
+        let mut sequential = C::default();
+        deltas.iter().for_each(|delta| sequential.merge(delta));
+        for replica in replicas.iter() {
+            assert_eq!(replica.value(), sequential.value());
+        }
+    }
+
+    // every index at least once, some twice, shuffled
+    fn delivery(n: usize) -> impl Strategy<Value = Vec<usize>> {
+        prop::collection::vec(0..n.max(1), 0..=n).prop_flat_map(move |dups| {
+            let mut all: Vec<usize> = (0..n).collect();
+            all.extend(dups.into_iter().filter(|&i| i < n));
+            Just(all).prop_shuffle()
+        })
+    }
+
+    fn ops_and_orders<O: Debug + Clone>(
+        op: impl Strategy<Value = O> + Clone,
+    ) -> impl Strategy<Value = (Vec<(usize, O)>, Vec<Vec<usize>>)> {
+        prop::collection::vec((0..REPLICAS, op), 0..30).prop_flat_map(|ops| {
+            let orders = prop::collection::vec(delivery(ops.len()), REPLICAS);
+            (Just(ops), orders)
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn g_counter((ops, orders) in ops_and_orders(1..10u64)) {
+            converges(ops, orders, |c: &mut GCounter, id, n| c.increment(id, n));
+        }
+
+        #[test]
+        fn pn_counter((ops, orders) in ops_and_orders((any::<bool>(), 1..10u64))) {
+            converges(ops, orders, |c: &mut PNCounter, id, (inc, n)| {
+                if inc {
+                    c.increment(id, n)
+                } else {
+                    c.decrement(id, n)
+                }
+            });
+        }
+
+        #[test]
+        fn or_set((ops, orders) in ops_and_orders((any::<bool>(), 0..5u8))) {
+            converges(ops, orders, |s: &mut ORSet<u8>, id, (add, e)| {
+                if add {
+                    s.add(id, e)
+                } else {
+                    s.remove(&e)
+                }
+            });
+        }
+
+        #[test]
+        fn lww_register((ops, orders) in ops_and_orders(0..100u32)) {
+            converges(ops, orders, |r: &mut LWWRegister<u32>, id, v| r.set(id, v));
+        }
+
+        #[test]
+        fn or_map((ops, orders) in ops_and_orders((any::<bool>(), 0..3u8, 1..5u64))) {
+            converges(ops, orders, |m: &mut ORMap<u8, GCounter>, id, (update, k, n)| {
+                if update {
+                    m.update(id, k, |c| c.increment(id, n))
+                } else {
+                    m.remove(&k)
+                }
+            });
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{Crdt, ReplicaId};
+
+// Last-writer-wins register, ties of the timestamp are broken by the replica id.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LWWRegister<T> {
+    value: Option<T>,
+    timestamp: (u64, ReplicaId),
+}
+
+impl<T> Default for LWWRegister<T> {
+    fn default() -> Self {
+        LWWRegister {
+            value: None,
+            timestamp: (0, 0),
+        }
+    }
+}
+
+impl<T: Clone> LWWRegister<T> {
+    // the register is small, so the delta is the register itself
+    pub fn set(&mut self, replica: ReplicaId, value: T) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_micros() as u64);
+        // later writes on this replica always win, even if the clock goes backwards
+        self.timestamp = (now.max(self.timestamp.0 + 1), replica);
+        self.value = Some(value);
+        self.clone()
+    }
+
+    pub fn get(&self) -> Option<&T> {
+        self.value.as_ref()
+    }
+}
+
+impl<T: Clone + Send + 'static> Crdt for LWWRegister<T> {
+    type Value = Option<T>;
+
+    fn merge(&mut self, other: &Self) {
+        if other.timestamp > self.timestamp {
+            *self = other.clone();
+        }
+    }
+
+    fn value(&self) -> Option<T> {
+        self.value.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn last_write_wins() {
+        let mut a = LWWRegister::default();
+        let mut b = LWWRegister::default();
+        let first = a.set(1, "first");
+        b.merge(&first);
+        let second = b.set(2, "second");
+        a.merge(&second);
+        // an older write is ignored
+        a.merge(&first);
+        assert_eq!(a.get(), Some(&"second"));
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn tie_breaks_by_replica() {
+        let a = LWWRegister {
+            value: Some("a"),
+            timestamp: (1, 1),
+        };
+        let b = LWWRegister {
+            value: Some("b"),
+            timestamp: (1, 2),
+        };
+        let mut ab = a.clone();
+        ab.merge(&b);
+        let mut ba = b.clone();
+        ba.merge(&a);
+        assert_eq!(ab.value(), Some("b"));
+        assert_eq!(ab, ba);
+    }
+}
@@ -0,0 +1,283 @@
+use std::{
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use tokio::{task::JoinHandle, time};
+
+use super::{Crdt, ReplicaId};
+use crate::tokio::actor::{spawn, Actor, Context, Pid};
+
+type Update<C> = Box<dyn FnOnce(&mut C, ReplicaId) -> C + Send>;
+// the weak is gone once the peer's `Replica` is dropped
+type Peer<C> = (Pid<Request<C>, Response<C>>, Weak<()>);
+
+pub enum Request<C: Crdt> {
+    // the update returns its delta
+    Update(Update<C>),
+    // a delta or the full state of a peer
+    Merge(C),
+    AddPeer(Pid<Request<C>, Response<C>>, Weak<()>),
+    Gossip,
+    Value,
+    State,
+}
+
+pub enum Response<C: Crdt> {
+    Done,
+    Value(C::Value),
+    State(C),
+}
+
+// Deltas are buffered and sent to the peers on every gossip round, and the full state is sent
+// every `full_sync` rounds, so lost deltas and peers of peers catch up eventually.
+struct ReplicaActor<C: Crdt> {
+    id: ReplicaId,
+    state: C,
+    delta: Option<C>,
+    // replicas which gossip with each other would keep each other alive with their pids only
+    peers: Vec<Peer<C>>,
+    // rounds since the last full sync
+    round: u64,
+    full_sync: u64,
+}
+
+impl<C: Crdt> ReplicaActor<C> {
+    fn gossip(&mut self) {
+        self.peers
+            .retain(|(peer, alive)| alive.strong_count() > 0 && !peer.is_closed());
+        self.round += 1;
+        let msg = if self.round >= self.full_sync {
+            self.round = 0;
+            self.delta = None;
+            self.state.clone()
+        } else {
+            match self.delta.take() {
+                Some(delta) => delta,
+                None => return,
+            }
+        };
+        for (peer, _) in self.peers.iter() {
+            let _ = peer.tell(Request::Merge(msg.clone()));
+        }
+    }
+}
+
+impl<C: Crdt> Actor for ReplicaActor<C> {
+    type Request = Request<C>;
+    type Reply = Response<C>;
+
+    fn handle_call(&mut self, req: Request<C>, _ctx: &mut Context<Self>) -> Result<Response<C>> {
+        match req {
+            Request::Update(update) => {
+                let delta = update(&mut self.state, self.id);
+                match self.delta.as_mut() {
+                    Some(buffer) => buffer.merge(&delta),
+                    None => self.delta = Some(delta),
+                }
+            }
+            Request::Merge(other) => self.state.merge(&other),
+            Request::AddPeer(peer, alive) => self.peers.push((peer, alive)),
+            Request::Gossip => self.gossip(),
+            Request::Value => return Ok(Response::Value(self.state.value())),
+            Request::State => return Ok(Response::State(self.state.clone())),
+        }
+        Ok(Response::Done)
+    }
+}
+
+// Dropping it stops the gossip ticker and the peers drop their pids on their next gossip, so
+// the replica actor stops once other pids to it are dropped.
+pub struct Replica<C: Crdt> {
+    pid: Pid<Request<C>, Response<C>>,
+    ticker: JoinHandle<()>,
+    alive: Arc<()>,
+}
+
+impl<C: Crdt> Replica<C>
+where
+    C::Value: Send,
+{
+    // spawns the replica actor and a task which gossips every `interval`
+    pub fn spawn(id: ReplicaId, interval: Duration, full_sync: u64) -> Self {
+        let actor = ReplicaActor {
+            id,
+            state: C::default(),
+            delta: None,
+            peers: Vec::new(),
+            round: 0,
+            full_sync: full_sync.max(1),
+        };
+        let pid = spawn(actor, 1024);
+
+        // the ticker's pid keeps the mailbox open, it's aborted when the replica is dropped
+        let ticker = pid.clone();
+        let ticker = tokio::spawn(async move {
+            let mut interval = time::interval(interval);
+            loop {
+                interval.tick().await;
+                let _ = ticker.tell(Request::Gossip);
+            }
+        });
+
+        Replica {
+            pid,
+            ticker,
+            alive: Arc::new(()),
+        }
+    }
+
+    pub fn pid(&self) -> Pid<Request<C>, Response<C>> {
+        self.pid.clone()
+    }
+
+    pub async fn add_peer(&self, peer: &Replica<C>) -> Result<()> {
+        let alive = Arc::downgrade(&peer.alive);
+        self.pid.send(Request::AddPeer(peer.pid(), alive)).await?;
+        Ok(())
+    }
+
+    pub async fn update<F>(&self, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut C, ReplicaId) -> C + Send + 'static,
+    {
+        self.pid.send(Request::Update(Box::new(f))).await?;
+        Ok(())
+    }
+
+    pub async fn value(&self) -> Result<C::Value> {
+        match self.pid.send(Request::Value).await? {
+            Response::Value(value) => Ok(value),
+            _ => Err(anyhow!("unexpected response")),
+        }
+    }
+
+    pub async fn state(&self) -> Result<C> {
+        match self.pid.send(Request::State).await? {
+            Response::State(state) => Ok(state),
+            _ => Err(anyhow!("unexpected response")),
+        }
+    }
+}
+
+impl<C: Crdt> Drop for Replica<C> {
+    fn drop(&mut self) {
+        self.ticker.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokio::crdt::{counter::PNCounter, set::ORSet};
+
+    async fn cluster<C: Crdt>(size: u64, full_sync: u64) -> Vec<Replica<C>>
+    where
+        C::Value: Send,
+    {
+        let replicas: Vec<Replica<C>> = (1..=size)
+            .map(|id| Replica::spawn(id, Duration::from_millis(5), full_sync))
+            .collect();
+        for a in replicas.iter() {
+            for b in replicas.iter() {
+                if !a.pid.sender.same_channel(&b.pid.sender) {
+                    a.add_peer(b).await.unwrap();
+                }
+            }
+        }
+        replicas
+    }
+
+    async fn converged<C: Crdt>(replicas: &[Replica<C>]) -> C::Value
+    where
+        C::Value: Send + PartialEq,
+    {
+        loop {
+            time::sleep(Duration::from_millis(10)).await;
+            let mut values = Vec::new();
+            for replica in replicas {
+                values.push(replica.value().await.unwrap());
+            }
+            if values.windows(2).all(|w| w[0] == w[1]) {
+                return values.remove(0);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn gossip_deltas() {
+        let replicas = cluster::<PNCounter>(3, 1000).await;
+        replicas[0]
+            .update(|c, id| c.increment(id, 5))
+            .await
+            .unwrap();
+        replicas[1]
+            .update(|c, id| c.decrement(id, 2))
+            .await
+            .unwrap();
+        replicas[2]
+            .update(|c, id| c.increment(id, 1))
+            .await
+            .unwrap();
+        let value = time::timeout(Duration::from_secs(5), converged(&replicas)).await;
+        assert_eq!(value.unwrap(), 4);
+        // the full states converge too, not only the values
+        let state = replicas[0].state().await.unwrap();
+        for replica in replicas.iter().skip(1) {
+            assert_eq!(replica.state().await.unwrap(), state);
+        }
+    }
+
+    #[tokio::test]
+    async fn full_sync_reaches_peers_of_peers() {
+        // a line: 1 <-> 2 <-> 3, deltas aren't forwarded
+        let replicas: Vec<Replica<ORSet<u64>>> = (1..=3)
+            .map(|id| Replica::spawn(id, Duration::from_millis(5), 3))
+            .collect();
+        for (a, b) in [(0, 1), (1, 0), (1, 2), (2, 1)].iter() {
+            replicas[*a].add_peer(&replicas[*b]).await.unwrap();
+        }
+        replicas[0].update(|s, id| s.add(id, 1)).await.unwrap();
+        replicas[2].update(|s, id| s.add(id, 3)).await.unwrap();
+        let value = time::timeout(Duration::from_secs(5), converged(&replicas)).await;
+        assert_eq!(value.unwrap(), [1, 3].iter().copied().collect());
+    }
+
+    #[tokio::test]
+    async fn dropped_replica_stops() {
+        // holds a token only while its replica actor is alive
+        #[derive(Clone, Default)]
+        struct Tracked(Option<Arc<()>>);
+
+        impl Crdt for Tracked {
+            type Value = ();
+
+            fn merge(&mut self, _other: &Self) {}
+
+            fn value(&self) {}
+        }
+
+        let replicas = cluster::<Tracked>(2, 3).await;
+        let token = Arc::new(());
+        let alive = token.clone();
+        replicas[0]
+            .update(move |c, _| {
+                c.0 = Some(alive);
+                Tracked::default()
+            })
+            .await
+            .unwrap();
+
+        let mut replicas = replicas.into_iter();
+        drop(replicas.next());
+        let stopped = async {
+            while Arc::strong_count(&token) > 1 {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        time::timeout(Duration::from_secs(5), stopped)
+            .await
+            .unwrap();
+    }
+}
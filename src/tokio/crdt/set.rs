@@ -0,0 +1,128 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::{Crdt, ReplicaId};
+
+// every add is tagged uniquely by the replica and its own counter
+pub(super) type Tag = (ReplicaId, u64);
+
+// Observed-remove set: a remove only removes the adds it has seen, so a concurrent add wins.
+// Removed tags are kept as tombstones.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ORSet<E: Ord> {
+    adds: BTreeMap<E, BTreeSet<Tag>>,
+    removes: BTreeSet<Tag>,
+    clock: BTreeMap<ReplicaId, u64>,
+}
+
+impl<E: Ord> Default for ORSet<E> {
+    fn default() -> Self {
+        ORSet {
+            adds: BTreeMap::new(),
+            removes: BTreeSet::new(),
+            clock: BTreeMap::new(),
+        }
+    }
+}
+
+impl<E: Ord + Clone> ORSet<E> {
+    pub fn add(&mut self, replica: ReplicaId, e: E) -> Self {
+        let counter = self.clock.entry(replica).or_insert(0);
+        *counter += 1;
+        let tag = (replica, *counter);
+        self.adds.entry(e.clone()).or_default().insert(tag);
+
+        let mut delta = ORSet::default();
+        delta.adds.entry(e).or_default().insert(tag);
+        delta.clock.insert(replica, tag.1);
+        delta
+    }
+
+    // the delta of an add done before, for a replica which may not have seen it yet
+    pub(super) fn added(&self, e: E, tag: Tag) -> Self {
+        let mut delta = ORSet::default();
+        delta.adds.entry(e).or_default().insert(tag);
+        delta.clock.insert(tag.0, tag.1);
+        delta
+    }
+
+    pub fn remove(&mut self, e: &E) -> Self {
+        let mut delta = ORSet::default();
+        if let Some(tags) = self.adds.get(e) {
+            delta.removes = tags.difference(&self.removes).copied().collect();
+        }
+        self.removes.extend(delta.removes.iter().copied());
+        delta
+    }
+
+    pub fn contains(&self, e: &E) -> bool {
+        self.adds
+            .get(e)
+            .is_some_and(|tags| tags.iter().any(|tag| !self.removes.contains(tag)))
+    }
+
+    // the adds of the element which aren't removed
+    pub(super) fn tags(&self, e: &E) -> BTreeSet<Tag> {
+        self.adds
+            .get(e)
+            .map(|tags| tags.difference(&self.removes).copied().collect())
+            .unwrap_or_default()
+    }
+}
+
+impl<E: Ord + Clone + Send + 'static> Crdt for ORSet<E> {
+    type Value = BTreeSet<E>;
+
+    fn merge(&mut self, other: &Self) {
+        for (e, tags) in other.adds.iter() {
+            self.adds
+                .entry(e.clone())
+                .or_default()
+                .extend(tags.iter().copied());
+        }
+        self.removes.extend(other.removes.iter().copied());
+        for (&replica, &counter) in other.clock.iter() {
+            let current = self.clock.entry(replica).or_insert(0);
+            *current = (*current).max(counter);
+        }
+    }
+
+    fn value(&self) -> BTreeSet<E> {
+        self.adds
+            .keys()
+            .filter(|e| self.contains(e))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_and_remove() {
+        let mut set = ORSet::default();
+        set.add(1, "a");
+        set.add(1, "b");
+        set.remove(&"a");
+        assert!(!set.contains(&"a"));
+        assert!(set.contains(&"b"));
+        set.add(1, "a");
+        assert!(set.contains(&"a"));
+    }
+
+    #[test]
+    fn concurrent_add_wins() {
+        let mut a = ORSet::default();
+        let delta = a.add(1, "x");
+        let mut b = ORSet::default();
+        b.merge(&delta);
+
+        let removed = a.remove(&"x");
+        let added = b.add(2, "x");
+        a.merge(&added);
+        b.merge(&removed);
+        assert!(a.contains(&"x"));
+        assert_eq!(a.value(), b.value());
+    }
+}
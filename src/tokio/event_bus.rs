@@ -0,0 +1,320 @@
+// Topics are dot separated words, like `orders.created`. Patterns can use wildcards:
+//  - `*` matches exactly one word
+//  - `#` matches zero or more words
+// Subscribers are pruned once their mailbox is closed. Events are delivered with `tell`, which
+// doesn't block the bus, so a subscriber whose mailbox is full misses the event and publish
+// reports it.
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use tokio::sync::{oneshot, watch};
+
+use super::actor::{spawn, Actor, Context, Pid};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event<E> {
+    pub topic: String,
+    pub payload: E,
+}
+
+pub(crate) enum BusRequest<E, R> {
+    // the sender is dropped with the subscription, which stops its watcher
+    Subscribe(String, Pid<Event<E>, R>, oneshot::Sender<()>),
+    Unsubscribe(usize),
+    Publish(String, E),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BusReply {
+    Subscribed(usize),
+    Unsubscribed(bool),
+    Published(Delivery),
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Delivery {
+    pub delivered: usize,
+    // ids of the subscriptions whose mailbox was full
+    pub missed: Vec<usize>,
+}
+
+struct Subscription<E, R> {
+    id: usize,
+    pattern: String,
+    pid: Pid<Event<E>, R>,
+    _watcher: oneshot::Sender<()>,
+}
+
+struct Bus<E, R> {
+    next_id: usize,
+    subscriptions: Vec<Subscription<E, R>>,
+}
+
+impl<E: Clone, R> Actor for Bus<E, R> {
+    type Request = BusRequest<E, R>;
+    type Reply = BusReply;
+
+    fn handle_call(&mut self, req: Self::Request, _ctx: &mut Context<Self>) -> Result<BusReply> {
+        match req {
+            BusRequest::Subscribe(pattern, pid, watcher) => {
+                self.next_id += 1;
+                self.subscriptions.push(Subscription {
+                    id: self.next_id,
+                    pattern,
+                    pid,
+                    _watcher: watcher,
+                });
+                Ok(BusReply::Subscribed(self.next_id))
+            }
+            BusRequest::Unsubscribe(id) => {
+                let len = self.subscriptions.len();
+                self.subscriptions.retain(|s| s.id != id);
+                Ok(BusReply::Unsubscribed(self.subscriptions.len() < len))
+            }
+            BusRequest::Publish(topic, payload) => {
+                let mut delivery = Delivery::default();
+                for s in self.subscriptions.iter() {
+                    if !matches(&s.pattern, &topic) {
+                        continue;
+                    }
+                    let event = Event {
+                        topic: topic.clone(),
+                        payload: payload.clone(),
+                    };
+                    if s.pid.tell(event).is_ok() {
+                        delivery.delivered += 1;
+                    } else if !s.pid.is_closed() {
+                        delivery.missed.push(s.id);
+                    }
+                }
+                // the watcher of a closed subscriber may not have unsubscribed it yet
+                self.subscriptions.retain(|s| !s.pid.is_closed());
+                Ok(BusReply::Published(delivery))
+            }
+        }
+    }
+}
+
+pub fn matches(pattern: &str, topic: &str) -> bool {
+    let pattern: Vec<&str> = pattern.split('.').collect();
+    let topic: Vec<&str> = topic.split('.').collect();
+    matches_words(&pattern, &topic)
+}
+
+fn matches_words(pattern: &[&str], topic: &[&str]) -> bool {
+    match (pattern.first(), topic.first()) {
+        (None, None) => true,
+        (Some(&"#"), _) => {
+            matches_words(&pattern[1..], topic)
+                || (!topic.is_empty() && matches_words(pattern, &topic[1..]))
+        }
+        (Some(&"*"), Some(_)) => matches_words(&pattern[1..], &topic[1..]),
+        (Some(p), Some(t)) if p == t => matches_words(&pattern[1..], &topic[1..]),
+        _ => false,
+    }
+}
+
+pub struct EventBus<E, R> {
+    pid: Pid<BusRequest<E, R>, BusReply>,
+    // the sender is dropped with the last handle, watchers stop then and the bus with them
+    _handles: Arc<watch::Sender<()>>,
+    dropped: watch::Receiver<()>,
+}
+
+impl<E, R> Clone for EventBus<E, R> {
+    fn clone(&self) -> Self {
+        EventBus {
+            pid: self.pid.clone(),
+            _handles: self._handles.clone(),
+            dropped: self.dropped.clone(),
+        }
+    }
+}
+
+impl<E, R> EventBus<E, R>
+where
+    E: Clone + Send + 'static,
+    R: Send + 'static,
+{
+    pub fn new(mailbox: usize) -> Self {
+        let bus = Bus {
+            next_id: 0,
+            subscriptions: Vec::new(),
+        };
+        let (handles, dropped) = watch::channel(());
+        EventBus {
+            pid: spawn(bus, mailbox),
+            _handles: Arc::new(handles),
+            dropped,
+        }
+    }
+
+    // the subscription is removed when the subscriber's mailbox is closed
+    pub async fn subscribe(&self, pattern: &str, pid: Pid<Event<E>, R>) -> Result<usize> {
+        let (watcher, unsubscribed) = oneshot::channel();
+        let req = BusRequest::Subscribe(pattern.to_string(), pid.clone(), watcher);
+        let id = match self.pid.send(req).await? {
+            BusReply::Subscribed(id) => id,
+            _ => return Err(anyhow!("unexpected response")),
+        };
+
+        // the watcher's pids would keep the bus and the subscriber alive, so it stops when the
+        // subscription is removed or the bus is dropped
+        let bus = self.pid.clone();
+        let mut dropped = self.dropped.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = pid.closed() => {
+                    let _ = bus.send(BusRequest::Unsubscribe(id)).await;
+                }
+                _ = unsubscribed => {}
+                _ = dropped.changed() => {}
+            }
+        });
+        Ok(id)
+    }
+
+    pub async fn unsubscribe(&self, id: usize) -> Result<bool> {
+        match self.pid.send(BusRequest::Unsubscribe(id)).await? {
+            BusReply::Unsubscribed(removed) => Ok(removed),
+            _ => Err(anyhow!("unexpected response")),
+        }
+    }
+
+    pub async fn publish(&self, topic: &str, payload: E) -> Result<Delivery> {
+        let req = BusRequest::Publish(topic.to_string(), payload);
+        match self.pid.send(req).await? {
+            BusReply::Published(delivery) => Ok(delivery),
+            _ => Err(anyhow!("unexpected response")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+
+    use super::*;
+
+    struct Subscriber {
+        events: mpsc::UnboundedSender<Event<usize>>,
+    }
+
+    impl Actor for Subscriber {
+        type Request = Event<usize>;
+        type Reply = ();
+
+        fn handle_call(&mut self, event: Event<usize>, _ctx: &mut Context<Self>) -> Result<()> {
+            let _ = self.events.send(event);
+            Ok(())
+        }
+    }
+
+    fn subscriber() -> (Pid<Event<usize>, ()>, mpsc::UnboundedReceiver<Event<usize>>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        (spawn(Subscriber { events: tx }, 20), rx)
+    }
+
+    fn event(topic: &str, payload: usize) -> Event<usize> {
+        Event {
+            topic: topic.to_string(),
+            payload,
+        }
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("orders.created", "orders.created"));
+        assert!(!matches("orders.created", "orders.deleted"));
+        assert!(matches("orders.*", "orders.created"));
+        assert!(!matches("orders.*", "orders"));
+        assert!(!matches("orders.*", "orders.eu.created"));
+        assert!(matches("orders.#", "orders"));
+        assert!(matches("orders.#", "orders.eu.created"));
+        assert!(matches("#.created", "orders.eu.created"));
+        assert!(matches("*.#.created", "orders.created"));
+        assert!(!matches("*.#.created", "created"));
+        assert!(matches("#", "anything.at.all"));
+    }
+
+    #[tokio::test]
+    async fn publish_to_matching_subscribers() {
+        let bus = EventBus::new(20);
+        let (pid1, mut rx1) = subscriber();
+        let (pid2, mut rx2) = subscriber();
+        bus.subscribe("orders.*", pid1).await.unwrap();
+        let id = bus.subscribe("#.created", pid2).await.unwrap();
+
+        assert_eq!(bus.publish("orders.created", 1).await.unwrap().delivered, 2);
+        assert_eq!(bus.publish("orders.deleted", 2).await.unwrap().delivered, 1);
+        assert_eq!(bus.publish("users.created", 3).await.unwrap().delivered, 1);
+        assert_eq!(bus.publish("users.deleted", 4).await.unwrap().delivered, 0);
+
+        assert_eq!(rx1.recv().await, Some(event("orders.created", 1)));
+        assert_eq!(rx1.recv().await, Some(event("orders.deleted", 2)));
+        assert_eq!(rx2.recv().await, Some(event("orders.created", 1)));
+        assert_eq!(rx2.recv().await, Some(event("users.created", 3)));
+
+        assert!(bus.unsubscribe(id).await.unwrap());
+        assert!(!bus.unsubscribe(id).await.unwrap());
+        assert_eq!(bus.publish("orders.created", 5).await.unwrap().delivered, 1);
+    }
+
+    #[tokio::test]
+    async fn prune_dead_subscribers() {
+        let bus = EventBus::new(20);
+        let (pid, _rx) = subscriber();
+        bus.subscribe("#", pid).await.unwrap();
+
+        let (sender, receiver) = tokio::sync::mpsc::channel(1);
+        let dead: Pid<Event<usize>, ()> = Pid { sender };
+        let id = bus.subscribe("#", dead).await.unwrap();
+        assert_eq!(bus.publish("orders.created", 1).await.unwrap().delivered, 2);
+
+        // pruned without a publish
+        drop(receiver);
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        assert!(!bus.unsubscribe(id).await.unwrap());
+        assert_eq!(bus.publish("orders.created", 2).await.unwrap().delivered, 1);
+    }
+
+    #[tokio::test]
+    async fn report_missed_events() {
+        let bus = EventBus::new(20);
+        let (pid, _rx) = subscriber();
+        bus.subscribe("#", pid).await.unwrap();
+
+        // nobody receives, so the mailbox is full after one event
+        let (sender, _receiver) = tokio::sync::mpsc::channel(1);
+        let slow: Pid<Event<usize>, ()> = Pid { sender };
+        let id = bus.subscribe("#", slow).await.unwrap();
+
+        let delivery = bus.publish("orders.created", 1).await.unwrap();
+        assert_eq!(delivery.delivered, 2);
+        assert!(delivery.missed.is_empty());
+        let delivery = bus.publish("orders.created", 2).await.unwrap();
+        assert_eq!(delivery.delivered, 1);
+        assert_eq!(delivery.missed, vec![id]);
+    }
+
+    #[tokio::test]
+    async fn dropped_bus_releases_subscribers() {
+        let bus = EventBus::new(20);
+        let (pid, mut rx) = subscriber();
+        bus.subscribe("#", pid).await.unwrap();
+
+        // a clone keeps the bus alive
+        let clone = bus.clone();
+        drop(bus);
+        assert_eq!(
+            clone.publish("orders.created", 1).await.unwrap().delivered,
+            1
+        );
+        assert_eq!(rx.recv().await, Some(event("orders.created", 1)));
+
+        // the subscriber stops, and drops its sender, once nothing refers to it
+        drop(clone);
+        let stopped = tokio::time::timeout(std::time::Duration::from_secs(1), rx.recv());
+        assert_eq!(stopped.await.unwrap(), None);
+    }
+}
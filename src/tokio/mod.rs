@@ -1,2 +1,11 @@
 mod actor;
+mod config;
+mod crdt;
+mod event_bus;
+mod raft;
+mod rate_limit;
+mod reactive;
+mod resilient;
+mod saga;
+mod statem;
 mod watch;
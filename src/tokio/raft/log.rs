@@ -0,0 +1,182 @@
+use super::node::NodeId;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntryData<C> {
+    // appended by a new leader, so entries of previous terms can be committed
+    Noop,
+    Command(C),
+    // members of the cluster, it takes effect as soon as it's appended
+    Config(Vec<NodeId>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry<C> {
+    pub term: u64,
+    pub data: EntryData<C>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot<S> {
+    pub index: u64,
+    pub term: u64,
+    pub members: Vec<NodeId>,
+    pub state: S,
+}
+
+// Entries after the snapshot, `entries[i]` is at index `snapshot.index + 1 + i`.
+// Indexes start at 1, index 0 is the empty snapshot.
+pub struct Log<C> {
+    snapshot_index: u64,
+    snapshot_term: u64,
+    entries: Vec<Entry<C>>,
+}
+
+impl<C: Clone> Log<C> {
+    pub fn new() -> Self {
+        Log {
+            snapshot_index: 0,
+            snapshot_term: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn snapshot_index(&self) -> u64 {
+        self.snapshot_index
+    }
+
+    pub fn last_index(&self) -> u64 {
+        self.snapshot_index + self.entries.len() as u64
+    }
+
+    pub fn last_term(&self) -> u64 {
+        self.entries.last().map_or(self.snapshot_term, |e| e.term)
+    }
+
+    // None if the entry is compacted or doesn't exist
+    pub fn get(&self, index: u64) -> Option<&Entry<C>> {
+        if index <= self.snapshot_index {
+            return None;
+        }
+        self.entries.get((index - self.snapshot_index - 1) as usize)
+    }
+
+    pub fn term_at(&self, index: u64) -> Option<u64> {
+        if index == self.snapshot_index {
+            return Some(self.snapshot_term);
+        }
+        self.get(index).map(|e| e.term)
+    }
+
+    pub fn entries_from(&self, index: u64, max: usize) -> Vec<Entry<C>> {
+        let start = (index.max(self.snapshot_index + 1) - self.snapshot_index - 1) as usize;
+        let end = (start + max).min(self.entries.len());
+        self.entries
+            .get(start..end)
+            .map(|entries| entries.to_vec())
+            .unwrap_or_default()
+    }
+
+    pub fn append(&mut self, entry: Entry<C>) -> u64 {
+        self.entries.push(entry);
+        self.last_index()
+    }
+
+    // remove the entry at index and all that follow it
+    pub fn truncate(&mut self, index: u64) {
+        if index > self.snapshot_index {
+            self.entries
+                .truncate((index - self.snapshot_index - 1) as usize);
+        }
+    }
+
+    // drop entries up to index, they're covered by a snapshot
+    pub fn compact(&mut self, index: u64, term: u64) {
+        if index <= self.snapshot_index {
+            return;
+        }
+        let keep = if self.term_at(index) == Some(term) {
+            self.entries
+                .split_off((index - self.snapshot_index) as usize)
+        } else {
+            Vec::new()
+        };
+        self.entries = keep;
+        self.snapshot_index = index;
+        self.snapshot_term = term;
+    }
+
+    // the latest config up to index, None if it's in the snapshot
+    pub fn config_at(&self, index: u64) -> Option<&Vec<NodeId>> {
+        (self.snapshot_index + 1..=index.min(self.last_index()))
+            .rev()
+            .filter_map(|i| self.get(i))
+            .find_map(|e| match &e.data {
+                EntryData::Config(members) => Some(members),
+                _ => None,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(term: u64) -> Entry<u64> {
+        Entry {
+            term,
+            data: EntryData::Command(term),
+        }
+    }
+
+    #[test]
+    fn append_and_truncate() {
+        let mut log = Log::new();
+        assert_eq!(log.last_index(), 0);
+        assert_eq!(log.term_at(0), Some(0));
+        assert_eq!(log.append(entry(1)), 1);
+        assert_eq!(log.append(entry(1)), 2);
+        assert_eq!(log.append(entry(2)), 3);
+        assert_eq!(log.last_term(), 2);
+        assert_eq!(log.entries_from(2, 10), vec![entry(1), entry(2)]);
+        assert_eq!(log.entries_from(2, 1), vec![entry(1)]);
+        assert_eq!(log.entries_from(4, 10), vec![]);
+
+        log.truncate(2);
+        assert_eq!(log.last_index(), 1);
+        assert_eq!(log.term_at(2), None);
+    }
+
+    #[test]
+    fn compact() {
+        let mut log = Log::new();
+        for term in 1..=5 {
+            log.append(entry(term));
+        }
+        log.compact(3, 3);
+        assert_eq!(log.snapshot_index(), 3);
+        assert_eq!(log.last_index(), 5);
+        assert_eq!(log.get(3), None);
+        assert_eq!(log.term_at(3), Some(3));
+        assert_eq!(log.entries_from(1, 10), vec![entry(4), entry(5)]);
+
+        // the snapshot conflicts with the log, so the log is dropped
+        log.compact(6, 7);
+        assert_eq!(log.last_index(), 6);
+        assert_eq!(log.last_term(), 7);
+    }
+
+    #[test]
+    fn config() {
+        let mut log = Log::new();
+        log.append(entry(1));
+        log.append(Entry {
+            term: 1,
+            data: EntryData::Config(vec![1, 2]),
+        });
+        log.append(entry(1));
+        assert_eq!(log.config_at(1), None);
+        assert_eq!(log.config_at(3), Some(&vec![1, 2]));
+        log.compact(2, 1);
+        assert_eq!(log.config_at(3), None);
+    }
+}
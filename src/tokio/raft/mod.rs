@@ -0,0 +1,5 @@
+// https://raft.github.io/raft.pdf
+// Membership changes are single-server changes from https://github.com/ongardie/dissertation
+mod log;
+mod node;
+mod transport;
@@ -0,0 +1,1016 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap, HashSet},
+    fmt::Debug,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use tokio::{task::JoinHandle, time};
+
+use super::{
+    log::{Entry, EntryData, Log, Snapshot},
+    transport::Transport,
+};
+use crate::tokio::actor::{spawn, Actor, Context, Pid};
+
+pub type NodeId = u64;
+
+pub trait StateMachine: Clone + Send + 'static {
+    type Command: Clone + Debug + Send + 'static;
+    fn apply(&mut self, cmd: &Self::Command);
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub tick: Duration,
+    // the election timeout is randomized in [election_ticks, 2 * election_ticks)
+    pub election_ticks: u64,
+    pub heartbeat_ticks: u64,
+    // applied entries kept in the log before it's compacted into a snapshot
+    pub snapshot_threshold: u64,
+    // max entries in one AppendEntries
+    pub max_entries: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            tick: Duration::from_millis(10),
+            election_ticks: 15,
+            heartbeat_ticks: 3,
+            snapshot_threshold: 1000,
+            max_entries: 64,
+        }
+    }
+}
+
+pub enum Message<S: StateMachine> {
+    RequestVote {
+        term: u64,
+        last_log_index: u64,
+        last_log_term: u64,
+    },
+    Vote {
+        term: u64,
+        granted: bool,
+    },
+    AppendEntries {
+        term: u64,
+        prev_index: u64,
+        prev_term: u64,
+        entries: Vec<Entry<S::Command>>,
+        commit: u64,
+    },
+    // on success the last index replicated, otherwise a hint where the logs match
+    AppendReply {
+        term: u64,
+        success: bool,
+        match_index: u64,
+    },
+    InstallSnapshot {
+        term: u64,
+        snapshot: Snapshot<S>,
+    },
+}
+
+impl<S: StateMachine> Message<S> {
+    fn term(&self) -> u64 {
+        match self {
+            Message::RequestVote { term, .. }
+            | Message::Vote { term, .. }
+            | Message::AppendEntries { term, .. }
+            | Message::AppendReply { term, .. }
+            | Message::InstallSnapshot { term, .. } => *term,
+        }
+    }
+}
+
+pub enum Request<S: StateMachine> {
+    Tick,
+    Message(NodeId, Message<S>),
+    Propose(S::Command),
+    AddNode(NodeId),
+    RemoveNode(NodeId),
+    Status,
+    State,
+}
+
+pub enum Response<S> {
+    Done,
+    // index of the appended entry, it may still be lost if the leader changes
+    Proposed(u64),
+    Status(Status),
+    State(S),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Status {
+    pub id: NodeId,
+    pub role: Role,
+    pub term: u64,
+    pub leader: Option<NodeId>,
+    pub commit_index: u64,
+    pub last_applied: u64,
+    pub snapshot_index: u64,
+    pub members: Vec<NodeId>,
+}
+
+// Term, vote and log are kept in memory only, a node can't recover from a crash.
+pub struct Node<S: StateMachine, T> {
+    id: NodeId,
+    config: Config,
+    transport: T,
+    role: Role,
+    term: u64,
+    voted_for: Option<NodeId>,
+    leader: Option<NodeId>,
+    log: Log<S::Command>,
+    commit_index: u64,
+    last_applied: u64,
+    state: S,
+    snapshot: Snapshot<S>,
+    votes: HashSet<NodeId>,
+    next_index: HashMap<NodeId, u64>,
+    match_index: HashMap<NodeId, u64>,
+    elapsed: u64,
+    timeout: u64,
+    rng: u64,
+}
+
+impl<S: StateMachine, T: Transport<S>> Node<S, T> {
+    // a node joining an existing cluster starts with no members and waits for the leader
+    pub fn new(id: NodeId, members: Vec<NodeId>, state: S, transport: T, config: Config) -> Self {
+        let snapshot = Snapshot {
+            index: 0,
+            term: 0,
+            members,
+            state: state.clone(),
+        };
+        let mut node = Node {
+            id,
+            config,
+            transport,
+            role: Role::Follower,
+            term: 0,
+            voted_for: None,
+            leader: None,
+            log: Log::new(),
+            commit_index: 0,
+            last_applied: 0,
+            state,
+            snapshot,
+            votes: HashSet::new(),
+            next_index: HashMap::new(),
+            match_index: HashMap::new(),
+            elapsed: 0,
+            timeout: 0,
+            rng: RandomState::new().build_hasher().finish() | 1,
+        };
+        node.reset_timeout();
+        node
+    }
+
+    fn members(&self) -> Vec<NodeId> {
+        match self.log.config_at(self.log.last_index()) {
+            Some(members) => members.clone(),
+            None => self.snapshot.members.clone(),
+        }
+    }
+
+    fn peers(&self) -> Vec<NodeId> {
+        let mut peers = self.members();
+        peers.retain(|&id| id != self.id);
+        peers
+    }
+
+    fn quorum(members: &[NodeId]) -> usize {
+        members.len() / 2 + 1
+    }
+
+    fn send(&self, to: NodeId, msg: Message<S>) {
+        self.transport.send(self.id, to, msg);
+    }
+
+    fn reset_timeout(&mut self) {
+        // xorshift64
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.elapsed = 0;
+        self.timeout = self.config.election_ticks + self.rng % self.config.election_ticks.max(1);
+    }
+
+    fn tick(&mut self) {
+        self.elapsed += 1;
+        match self.role {
+            Role::Leader if self.elapsed >= self.config.heartbeat_ticks => {
+                self.elapsed = 0;
+                self.broadcast();
+            }
+            Role::Leader => {}
+            _ if self.elapsed >= self.timeout => self.start_election(),
+            _ => {}
+        }
+    }
+
+    fn become_follower(&mut self, term: u64, leader: Option<NodeId>) {
+        if term > self.term {
+            self.term = term;
+            self.voted_for = None;
+        }
+        self.role = Role::Follower;
+        self.leader = leader;
+    }
+
+    fn start_election(&mut self) {
+        self.reset_timeout();
+        // removed or not yet added nodes don't disrupt the cluster
+        if !self.members().contains(&self.id) {
+            return;
+        }
+
+        self.term += 1;
+        self.role = Role::Candidate;
+        self.voted_for = Some(self.id);
+        self.leader = None;
+        self.votes = [self.id].iter().copied().collect();
+        if self.votes.len() >= Self::quorum(&self.members()) {
+            self.become_leader();
+            return;
+        }
+
+        for peer in self.peers() {
+            self.send(
+                peer,
+                Message::RequestVote {
+                    term: self.term,
+                    last_log_index: self.log.last_index(),
+                    last_log_term: self.log.last_term(),
+                },
+            );
+        }
+    }
+
+    fn become_leader(&mut self) {
+        self.role = Role::Leader;
+        self.leader = Some(self.id);
+        self.elapsed = 0;
+        self.next_index.clear();
+        self.match_index.clear();
+        self.log.append(Entry {
+            term: self.term,
+            data: EntryData::Noop,
+        });
+        self.broadcast();
+    }
+
+    fn broadcast(&mut self) {
+        for peer in self.peers() {
+            self.replicate(peer);
+        }
+        // a single node cluster commits right away
+        self.maybe_commit();
+    }
+
+    fn replicate(&mut self, peer: NodeId) {
+        let last_index = self.log.last_index();
+        let next = *self.next_index.entry(peer).or_insert(last_index + 1);
+        if next <= self.log.snapshot_index() {
+            let msg = Message::InstallSnapshot {
+                term: self.term,
+                snapshot: self.snapshot.clone(),
+            };
+            self.send(peer, msg);
+            return;
+        }
+
+        let prev_index = next - 1;
+        let msg = Message::AppendEntries {
+            term: self.term,
+            prev_index,
+            prev_term: self.log.term_at(prev_index).unwrap_or(0),
+            entries: self.log.entries_from(next, self.config.max_entries),
+            commit: self.commit_index,
+        };
+        self.send(peer, msg);
+    }
+
+    fn handle_message(&mut self, from: NodeId, msg: Message<S>) {
+        // a node which has heard from the leader recently ignores votes, so removed nodes can't
+        // disrupt the cluster with higher terms
+        if let Message::RequestVote { .. } = msg {
+            if self.leader.is_some() && self.elapsed < self.config.election_ticks {
+                return;
+            }
+        }
+
+        if msg.term() > self.term {
+            self.become_follower(msg.term(), None);
+        }
+
+        match msg {
+            Message::RequestVote {
+                term,
+                last_log_index,
+                last_log_term,
+            } => {
+                let up_to_date = (last_log_term, last_log_index)
+                    >= (self.log.last_term(), self.log.last_index());
+                let granted =
+                    term == self.term && up_to_date && self.voted_for.is_none_or(|id| id == from);
+                if granted {
+                    self.voted_for = Some(from);
+                    self.reset_timeout();
+                }
+                let term = self.term;
+                self.send(from, Message::Vote { term, granted });
+            }
+            Message::Vote { term, granted } => {
+                if self.role == Role::Candidate && term == self.term && granted {
+                    self.votes.insert(from);
+                    let members = self.members();
+                    let votes = members.iter().filter(|id| self.votes.contains(id)).count();
+                    if votes >= Self::quorum(&members) {
+                        self.become_leader();
+                    }
+                }
+            }
+            Message::AppendEntries {
+                term,
+                prev_index,
+                prev_term,
+                entries,
+                commit,
+            } => {
+                if term < self.term {
+                    self.reply_append(from, false, 0);
+                    return;
+                }
+                self.become_follower(term, Some(from));
+                self.reset_timeout();
+                self.append_entries(from, prev_index, prev_term, entries, commit);
+            }
+            Message::AppendReply {
+                term,
+                success,
+                match_index,
+            } => {
+                if self.role != Role::Leader || term != self.term {
+                    return;
+                }
+                let next = self.next_index.get(&from).copied().unwrap_or(1);
+                if success {
+                    let matched = self.match_index.entry(from).or_insert(0);
+                    *matched = (*matched).max(match_index);
+                    let matched = *matched;
+                    self.next_index.insert(from, matched + 1);
+                    self.maybe_commit();
+                    if matched < self.log.last_index() {
+                        self.replicate(from);
+                    }
+                } else {
+                    let next = next.saturating_sub(1).min(match_index + 1).max(1);
+                    self.next_index.insert(from, next);
+                    self.replicate(from);
+                }
+            }
+            Message::InstallSnapshot { term, snapshot } => {
+                if term < self.term {
+                    self.reply_append(from, false, 0);
+                    return;
+                }
+                self.become_follower(term, Some(from));
+                self.reset_timeout();
+                let index = self.install_snapshot(snapshot);
+                self.reply_append(from, true, index);
+            }
+        }
+    }
+
+    fn reply_append(&self, to: NodeId, success: bool, match_index: u64) {
+        let msg = Message::AppendReply {
+            term: self.term,
+            success,
+            match_index,
+        };
+        self.send(to, msg);
+    }
+
+    fn append_entries(
+        &mut self,
+        from: NodeId,
+        prev_index: u64,
+        prev_term: u64,
+        entries: Vec<Entry<S::Command>>,
+        commit: u64,
+    ) {
+        if prev_index > self.log.last_index() {
+            self.reply_append(from, false, self.log.last_index());
+            return;
+        }
+        // entries up to the snapshot are committed, so they match the leader's
+        if prev_index >= self.log.snapshot_index()
+            && self.log.term_at(prev_index) != Some(prev_term)
+        {
+            self.reply_append(from, false, self.commit_index);
+            return;
+        }
+
+        let mut index = prev_index;
+        for entry in entries {
+            index += 1;
+            if index <= self.log.snapshot_index() {
+                continue;
+            }
+            match self.log.term_at(index) {
+                Some(term) if term == entry.term => {}
+                Some(_) => {
+                    self.log.truncate(index);
+                    self.log.append(entry);
+                }
+                None => {
+                    self.log.append(entry);
+                }
+            }
+        }
+
+        if commit > self.commit_index {
+            self.commit_index = commit.min(index);
+            self.apply();
+        }
+        self.reply_append(from, true, index);
+    }
+
+    // returns the last index known to match the leader
+    fn install_snapshot(&mut self, snapshot: Snapshot<S>) -> u64 {
+        if snapshot.index <= self.commit_index {
+            return self.commit_index;
+        }
+        self.log.compact(snapshot.index, snapshot.term);
+        self.state = snapshot.state.clone();
+        self.commit_index = snapshot.index;
+        self.last_applied = snapshot.index;
+        self.snapshot = snapshot;
+        self.commit_index
+    }
+
+    fn maybe_commit(&mut self) {
+        let members = self.members();
+        for index in (self.commit_index + 1..=self.log.last_index()).rev() {
+            // only entries of the current term are committed by counting replicas
+            if self.log.term_at(index) != Some(self.term) {
+                break;
+            }
+            let replicas = members
+                .iter()
+                .filter(|&&id| {
+                    id == self.id || self.match_index.get(&id).copied().unwrap_or(0) >= index
+                })
+                .count();
+            if replicas >= Self::quorum(&members) {
+                self.commit_index = index;
+                self.apply();
+                break;
+            }
+        }
+    }
+
+    fn has_pending_config(&self) -> bool {
+        (self.commit_index + 1..=self.log.last_index())
+            .filter_map(|i| self.log.get(i))
+            .any(|e| matches!(e.data, EntryData::Config(_)))
+    }
+
+    fn apply(&mut self) {
+        while self.last_applied < self.commit_index {
+            self.last_applied += 1;
+            if let Some(Entry {
+                data: EntryData::Command(cmd),
+                ..
+            }) = self.log.get(self.last_applied)
+            {
+                self.state.apply(cmd);
+            }
+        }
+
+        // a removed leader steps down once the change is committed
+        if self.role == Role::Leader
+            && !self.members().contains(&self.id)
+            && !self.has_pending_config()
+        {
+            self.role = Role::Follower;
+            self.leader = None;
+        }
+
+        if self.last_applied - self.snapshot.index >= self.config.snapshot_threshold {
+            self.take_snapshot();
+        }
+    }
+
+    fn take_snapshot(&mut self) {
+        let index = self.last_applied;
+        let term = self.log.term_at(index).unwrap_or(self.term);
+        let members = match self.log.config_at(index) {
+            Some(members) => members.clone(),
+            None => self.snapshot.members.clone(),
+        };
+        self.snapshot = Snapshot {
+            index,
+            term,
+            members,
+            state: self.state.clone(),
+        };
+        self.log.compact(index, term);
+    }
+
+    fn propose(&mut self, data: EntryData<S::Command>) -> Result<u64> {
+        if self.role != Role::Leader {
+            return Err(anyhow!("not leader, leader is {:?}", self.leader));
+        }
+        let index = self.log.append(Entry {
+            term: self.term,
+            data,
+        });
+        self.broadcast();
+        Ok(index)
+    }
+
+    // one node is added or removed at a time, so old and new majorities always overlap
+    fn change_members(&mut self, members: Vec<NodeId>) -> Result<u64> {
+        if self.role == Role::Leader && self.has_pending_config() {
+            return Err(anyhow!("a membership change is in progress"));
+        }
+        // a new leader must commit an entry first, see the bug in single-server changes
+        if self.role == Role::Leader && self.log.term_at(self.commit_index) != Some(self.term) {
+            return Err(anyhow!("leader has not committed an entry in its term"));
+        }
+        self.propose(EntryData::Config(members))
+    }
+
+    fn status(&self) -> Status {
+        Status {
+            id: self.id,
+            role: self.role,
+            term: self.term,
+            leader: self.leader,
+            commit_index: self.commit_index,
+            last_applied: self.last_applied,
+            snapshot_index: self.log.snapshot_index(),
+            members: self.members(),
+        }
+    }
+}
+
+impl<S: StateMachine, T: Transport<S>> Actor for Node<S, T> {
+    type Request = Request<S>;
+    type Reply = Response<S>;
+
+    fn handle_call(&mut self, req: Request<S>, _ctx: &mut Context<Self>) -> Result<Response<S>> {
+        match req {
+            Request::Tick => self.tick(),
+            Request::Message(from, msg) => self.handle_message(from, msg),
+            Request::Propose(cmd) => {
+                return self
+                    .propose(EntryData::Command(cmd))
+                    .map(Response::Proposed)
+            }
+            Request::AddNode(id) => {
+                let mut members = self.members();
+                if !members.contains(&id) {
+                    members.push(id);
+                }
+                return self.change_members(members).map(Response::Proposed);
+            }
+            Request::RemoveNode(id) => {
+                let mut members = self.members();
+                members.retain(|&m| m != id);
+                return self.change_members(members).map(Response::Proposed);
+            }
+            Request::Status => return Ok(Response::Status(self.status())),
+            Request::State => return Ok(Response::State(self.state.clone())),
+        }
+        Ok(Response::Done)
+    }
+}
+
+// Dropping it stops the ticker and leaves the transport, so the node actor stops once other
+// pids to it are dropped.
+pub struct RaftNode<S: StateMachine> {
+    id: NodeId,
+    pid: Pid<Request<S>, Response<S>>,
+    ticker: JoinHandle<()>,
+    transport: Box<dyn Transport<S>>,
+}
+
+impl<S: StateMachine> RaftNode<S> {
+    // spawns the node actor and a task which ticks it
+    pub fn spawn<T: Transport<S> + Clone>(
+        id: NodeId,
+        members: Vec<NodeId>,
+        state: S,
+        transport: T,
+        config: Config,
+    ) -> Self {
+        let tick = config.tick;
+        let pid = spawn(
+            Node::new(id, members, state, transport.clone(), config),
+            1024,
+        );
+
+        // the ticker's pid keeps the mailbox open, it's aborted when the node is dropped
+        let ticker = pid.clone();
+        let ticker = tokio::spawn(async move {
+            let mut interval = time::interval(tick);
+            loop {
+                interval.tick().await;
+                let _ = ticker.tell(Request::Tick);
+            }
+        });
+
+        RaftNode {
+            id,
+            pid,
+            ticker,
+            transport: Box::new(transport),
+        }
+    }
+
+    pub fn pid(&self) -> Pid<Request<S>, Response<S>> {
+        self.pid.clone()
+    }
+
+    // fails if this node isn't the leader
+    pub async fn propose(&self, cmd: S::Command) -> Result<u64> {
+        match self.pid.send(Request::Propose(cmd)).await? {
+            Response::Proposed(index) => Ok(index),
+            _ => Err(anyhow!("unexpected response")),
+        }
+    }
+
+    pub async fn add_node(&self, id: NodeId) -> Result<u64> {
+        match self.pid.send(Request::AddNode(id)).await? {
+            Response::Proposed(index) => Ok(index),
+            _ => Err(anyhow!("unexpected response")),
+        }
+    }
+
+    pub async fn remove_node(&self, id: NodeId) -> Result<u64> {
+        match self.pid.send(Request::RemoveNode(id)).await? {
+            Response::Proposed(index) => Ok(index),
+            _ => Err(anyhow!("unexpected response")),
+        }
+    }
+
+    pub async fn status(&self) -> Result<Status> {
+        match self.pid.send(Request::Status).await? {
+            Response::Status(status) => Ok(status),
+            _ => Err(anyhow!("unexpected response")),
+        }
+    }
+
+    // the state machine with all committed commands applied
+    pub async fn state(&self) -> Result<S> {
+        match self.pid.send(Request::State).await? {
+            Response::State(state) => Ok(state),
+            _ => Err(anyhow!("unexpected response")),
+        }
+    }
+}
+
+impl<S: StateMachine> Drop for RaftNode<S> {
+    fn drop(&mut self) {
+        self.ticker.abort();
+        self.transport.leave(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+
+    use super::*;
+    use crate::tokio::raft::transport::LocalTransport;
+
+    #[derive(Debug, Clone, Default, PartialEq)]
+    struct Appended(Vec<u64>);
+
+    impl StateMachine for Appended {
+        type Command = u64;
+
+        fn apply(&mut self, cmd: &u64) {
+            self.0.push(*cmd);
+        }
+    }
+
+    fn config() -> Config {
+        Config {
+            tick: Duration::from_millis(5),
+            election_ticks: 10,
+            heartbeat_ticks: 2,
+            ..Config::default()
+        }
+    }
+
+    // polls until `f` returns Some
+    async fn eventually<T, F, Fut>(mut f: F) -> T
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Option<T>>,
+    {
+        let deadline = time::Instant::now() + Duration::from_secs(10);
+        while time::Instant::now() < deadline {
+            if let Some(v) = f().await {
+                return v;
+            }
+            time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("timed out");
+    }
+
+    struct Cluster {
+        transport: LocalTransport<Appended>,
+        nodes: HashMap<NodeId, RaftNode<Appended>>,
+        config: Config,
+    }
+
+    impl Cluster {
+        fn new(size: u64, config: Config) -> Self {
+            let mut cluster = Cluster {
+                transport: LocalTransport::new(),
+                nodes: HashMap::new(),
+                config,
+            };
+            let members: Vec<NodeId> = (1..=size).collect();
+            for &id in members.iter() {
+                cluster.add(id, members.clone());
+            }
+            cluster
+        }
+
+        fn add(&mut self, id: NodeId, members: Vec<NodeId>) {
+            let node = RaftNode::spawn(
+                id,
+                members,
+                Appended::default(),
+                self.transport.clone(),
+                self.config.clone(),
+            );
+            self.transport.register(id, node.pid());
+            self.nodes.insert(id, node);
+        }
+
+        fn node(&self, id: NodeId) -> &RaftNode<Appended> {
+            &self.nodes[&id]
+        }
+
+        // the leader with the highest term among the nodes
+        async fn leader(&self, among: &[NodeId]) -> NodeId {
+            eventually(|| async move {
+                let mut leader = None;
+                for &id in among {
+                    let status = self.node(id).status().await.unwrap();
+                    if status.role == Role::Leader
+                        && leader.is_none_or(|(_, term)| status.term > term)
+                    {
+                        leader = Some((id, status.term));
+                    }
+                }
+                leader.map(|(id, _)| id)
+            })
+            .await
+        }
+
+        // proposes to the leader among the nodes until the command is applied on it
+        async fn propose(&self, among: &[NodeId], cmd: u64) {
+            loop {
+                let leader = self.leader(among).await;
+                if self.node(leader).propose(cmd).await.is_err() {
+                    continue;
+                }
+                let applied = eventually(|| async move {
+                    let status = self.node(leader).status().await.unwrap();
+                    if status.role != Role::Leader {
+                        return Some(false);
+                    }
+                    let state = self.node(leader).state().await.unwrap();
+                    Some(true).filter(|_| state.0.contains(&cmd))
+                })
+                .await;
+                if applied {
+                    return;
+                }
+            }
+        }
+
+        async fn converge(&self, among: &[NodeId], expected: &[u64]) {
+            for &id in among {
+                eventually(|| async move {
+                    let state = self.node(id).state().await.unwrap();
+                    Some(()).filter(|_| state.0 == expected)
+                })
+                .await;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn elect_leader() {
+        let cluster = Cluster::new(5, config());
+        let all = [1, 2, 3, 4, 5];
+        let leader = cluster.leader(&all).await;
+        let cluster = &cluster;
+        for &id in all.iter() {
+            eventually(|| async move {
+                let status = cluster.node(id).status().await.unwrap();
+                Some(()).filter(|_| status.leader == Some(leader))
+            })
+            .await;
+        }
+    }
+
+    #[tokio::test]
+    async fn replicate_with_partition() {
+        let cluster = Cluster::new(5, config());
+        let all = [1, 2, 3, 4, 5];
+        for cmd in 1..=3 {
+            cluster.propose(&all, cmd).await;
+        }
+        cluster.converge(&all, &[1, 2, 3]).await;
+
+        let old = cluster.leader(&all).await;
+        cluster.transport.isolate(old);
+        let others: Vec<NodeId> = all.iter().copied().filter(|&id| id != old).collect();
+        for cmd in 4..=5 {
+            cluster.propose(&others, cmd).await;
+        }
+        cluster.converge(&others, &[1, 2, 3, 4, 5]).await;
+
+        // the old leader can't commit anything on its own, the entry is dropped after healing
+        let _ = cluster.node(old).propose(99).await;
+        cluster.transport.heal();
+        cluster.converge(&all, &[1, 2, 3, 4, 5]).await;
+    }
+
+    #[tokio::test]
+    async fn split_brain() {
+        let cluster = Cluster::new(5, config());
+        let all = [1, 2, 3, 4, 5];
+        for cmd in 1..=3 {
+            cluster.propose(&all, cmd).await;
+        }
+        cluster.converge(&all, &[1, 2, 3]).await;
+
+        // the old leader is on the minority side
+        let old = cluster.leader(&all).await;
+        let mut others = all.iter().copied().filter(|&id| id != old);
+        let minority = vec![old, others.next().unwrap()];
+        let majority: Vec<NodeId> = others.collect();
+        cluster.transport.partition(&[&minority, &majority]);
+
+        let _ = cluster.node(old).propose(99).await;
+        for cmd in 4..=5 {
+            cluster.propose(&majority, cmd).await;
+        }
+        cluster.converge(&majority, &[1, 2, 3, 4, 5]).await;
+        assert_ne!(cluster.leader(&majority).await, old);
+        // the minority can't commit, neither the new entries nor its own
+        for &id in minority.iter() {
+            assert_eq!(cluster.node(id).state().await.unwrap().0, vec![1, 2, 3]);
+        }
+
+        cluster.transport.heal();
+        cluster.converge(&all, &[1, 2, 3, 4, 5]).await;
+    }
+
+    #[tokio::test]
+    async fn message_loss() {
+        let cluster = Cluster::new(5, config());
+        let all = [1, 2, 3, 4, 5];
+        cluster.transport.set_loss(0.2);
+        for cmd in 1..=10 {
+            cluster.propose(&all, cmd).await;
+        }
+        cluster.transport.set_loss(0.0);
+
+        // a proposal retried after a leader change may be applied twice
+        let expected = cluster
+            .node(cluster.leader(&all).await)
+            .state()
+            .await
+            .unwrap();
+        assert!((1..=10).all(|cmd| expected.0.contains(&cmd)));
+        cluster.converge(&all, &expected.0).await;
+    }
+
+    #[tokio::test]
+    async fn install_snapshot() {
+        let cluster = Cluster::new(
+            3,
+            Config {
+                snapshot_threshold: 5,
+                ..config()
+            },
+        );
+        let all = [1, 2, 3];
+        let leader = cluster.leader(&all).await;
+        let follower = all.iter().copied().find(|&id| id != leader).unwrap();
+        cluster.transport.isolate(follower);
+
+        let others: Vec<NodeId> = all.iter().copied().filter(|&id| id != follower).collect();
+        for cmd in 1..=20 {
+            cluster.propose(&others, cmd).await;
+        }
+        let leader = cluster.leader(&others).await;
+        assert!(cluster.node(leader).status().await.unwrap().snapshot_index > 0);
+
+        cluster.transport.heal();
+        let expected: Vec<u64> = (1..=20).collect();
+        cluster.converge(&all, &expected).await;
+        assert!(
+            cluster
+                .node(follower)
+                .status()
+                .await
+                .unwrap()
+                .snapshot_index
+                > 0
+        );
+    }
+
+    #[tokio::test]
+    async fn dropped_node_stops() {
+        // dropped with the node actor
+        #[derive(Clone)]
+        struct Tracked {
+            _alive: std::sync::Arc<()>,
+        }
+
+        impl StateMachine for Tracked {
+            type Command = ();
+
+            fn apply(&mut self, _cmd: &()) {}
+        }
+
+        let alive = std::sync::Arc::new(());
+        let transport = LocalTransport::new();
+        let node = RaftNode::spawn(
+            1,
+            vec![1],
+            Tracked {
+                _alive: alive.clone(),
+            },
+            transport.clone(),
+            config(),
+        );
+        transport.register(1, node.pid());
+        eventually(|| async { node.status().await.ok().filter(|s| s.role == Role::Leader) }).await;
+
+        drop(node);
+        eventually(|| async { Some(()).filter(|_| std::sync::Arc::strong_count(&alive) == 1) })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn change_members() {
+        let mut cluster = Cluster::new(3, config());
+        let all = [1, 2, 3];
+        cluster.propose(&all, 1).await;
+
+        // the new node has no members, so it waits for the leader instead of starting elections
+        cluster.add(4, vec![]);
+        let leader = cluster.leader(&all).await;
+        cluster.node(leader).add_node(4).await.unwrap();
+        cluster.propose(&all, 2).await;
+        cluster.converge(&[1, 2, 3, 4], &[1, 2]).await;
+        assert_eq!(
+            cluster.node(4).status().await.unwrap().members,
+            vec![1, 2, 3, 4]
+        );
+
+        // the leader removes itself and steps down after the change is committed
+        let leader = cluster.leader(&[1, 2, 3, 4]).await;
+        let node = cluster.node(leader);
+        eventually(|| async move { node.remove_node(leader).await.ok() }).await;
+        let others: Vec<NodeId> = [1, 2, 3, 4]
+            .iter()
+            .copied()
+            .filter(|&id| id != leader)
+            .collect();
+        let new_leader = cluster.leader(&others).await;
+        assert_ne!(new_leader, leader);
+        cluster.propose(&others, 3).await;
+        cluster.converge(&others, &[1, 2, 3]).await;
+
+        let status = cluster.node(leader).status().await.unwrap();
+        assert_ne!(status.role, Role::Leader);
+        assert!(!status.members.contains(&leader));
+    }
+}
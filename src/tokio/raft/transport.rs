@@ -0,0 +1,119 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap, HashSet},
+    hash::{BuildHasher, Hasher},
+    sync::{Arc, Mutex},
+};
+
+use super::node::{Message, NodeId, Request, Response, StateMachine};
+use crate::tokio::actor::Pid;
+
+// how a node reaches its peers, sending never blocks and messages may be lost
+pub trait Transport<S: StateMachine>: Send + 'static {
+    fn send(&self, from: NodeId, to: NodeId, msg: Message<S>);
+    // the node is dropped, release anything which keeps it alive
+    fn leave(&self, _id: NodeId) {}
+}
+
+struct Network<S: StateMachine> {
+    nodes: HashMap<NodeId, Pid<Request<S>, Response<S>>>,
+    // (from, to) links which drop every message
+    blocked: HashSet<(NodeId, NodeId)>,
+    // probability to drop a message, from 0.0 to 1.0
+    loss: f64,
+    rng: u64,
+}
+
+// In-process transport over node pids, with faults injected by tests.
+pub struct LocalTransport<S: StateMachine> {
+    network: Arc<Mutex<Network<S>>>,
+}
+
+impl<S: StateMachine> Clone for LocalTransport<S> {
+    fn clone(&self) -> Self {
+        LocalTransport {
+            network: self.network.clone(),
+        }
+    }
+}
+
+impl<S: StateMachine> LocalTransport<S> {
+    pub fn new() -> Self {
+        let network = Network {
+            nodes: HashMap::new(),
+            blocked: HashSet::new(),
+            loss: 0.0,
+            rng: RandomState::new().build_hasher().finish() | 1,
+        };
+        LocalTransport {
+            network: Arc::new(Mutex::new(network)),
+        }
+    }
+
+    pub fn register(&self, id: NodeId, pid: Pid<Request<S>, Response<S>>) {
+        self.network.lock().unwrap().nodes.insert(id, pid);
+    }
+
+    // cut the node off from all others
+    pub fn isolate(&self, id: NodeId) {
+        let mut network = self.network.lock().unwrap();
+        let others: Vec<NodeId> = network.nodes.keys().copied().collect();
+        for other in others {
+            network.blocked.insert((id, other));
+            network.blocked.insert((other, id));
+        }
+    }
+
+    // nodes can only talk to nodes in the same group
+    pub fn partition(&self, groups: &[&[NodeId]]) {
+        let mut network = self.network.lock().unwrap();
+        for (i, a) in groups.iter().enumerate() {
+            for b in groups.iter().skip(i + 1) {
+                for &x in a.iter() {
+                    for &y in b.iter() {
+                        network.blocked.insert((x, y));
+                        network.blocked.insert((y, x));
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn heal(&self) {
+        self.network.lock().unwrap().blocked.clear();
+    }
+
+    pub fn set_loss(&self, loss: f64) {
+        self.network.lock().unwrap().loss = loss;
+    }
+}
+
+impl<S: StateMachine> Network<S> {
+    // xorshift64
+    fn random(&mut self) -> f64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng % 10_000) as f64 / 10_000.0
+    }
+}
+
+impl<S: StateMachine> Transport<S> for LocalTransport<S> {
+    fn send(&self, from: NodeId, to: NodeId, msg: Message<S>) {
+        let mut network = self.network.lock().unwrap();
+        if network.blocked.contains(&(from, to)) {
+            return;
+        }
+        if network.loss > 0.0 && network.random() < network.loss {
+            return;
+        }
+        if let Some(pid) = network.nodes.get(&to) {
+            // a full mailbox loses the message too
+            let _ = pid.tell(Request::Message(from, msg));
+        }
+    }
+
+    // the registered pid would keep the node's mailbox open, and the node owns this transport
+    fn leave(&self, id: NodeId) {
+        self.network.lock().unwrap().nodes.remove(&id);
+    }
+}
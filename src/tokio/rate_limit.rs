@@ -0,0 +1,303 @@
+// Token bucket: https://en.wikipedia.org/wiki/Token_bucket
+//  - up to `burst` requests are handled at once, then `rate` per second
+//  - requests wait in the mailbox for a token, so senders get backpressure
+// Leaky bucket (as a queue): https://en.wikipedia.org/wiki/Leaky_bucket
+//  - requests are handled at a constant `rate` per second, without bursts
+//  - up to `burst` requests wait in the bucket, requests over it get an error reply
+//  - the bucket is the queue of one actor, so the limiter can't be shared by actors
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Result};
+use tokio::{
+    sync::mpsc::Receiver,
+    time::{self, Instant},
+};
+
+use super::actor::ActorMessage;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    TokenBucket,
+    LeakyBucket,
+}
+
+struct Bucket {
+    algorithm: Algorithm,
+    rate: f64,
+    burst: usize,
+    // available tokens, or free space of the leaky bucket
+    tokens: f64,
+    updated: Instant,
+    // a leaky bucket is used by an actor
+    leaking: bool,
+}
+
+impl Bucket {
+    fn refill(&mut self) {
+        if self.algorithm == Algorithm::TokenBucket {
+            let elapsed = self.updated.elapsed().as_secs_f64();
+            self.tokens = (self.tokens + elapsed * self.rate).min(self.burst as f64);
+        }
+        self.updated = Instant::now();
+    }
+}
+
+// Shared by clones, so the token level can be observed while the actor uses it.
+#[derive(Clone)]
+pub struct RateLimiter {
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn token_bucket(rate: u32, burst: usize) -> Result<Self> {
+        RateLimiter::new(Algorithm::TokenBucket, rate, burst)
+    }
+
+    pub fn leaky_bucket(rate: u32, burst: usize) -> Result<Self> {
+        RateLimiter::new(Algorithm::LeakyBucket, rate, burst)
+    }
+
+    fn new(algorithm: Algorithm, rate: u32, burst: usize) -> Result<Self> {
+        if rate == 0 {
+            bail!("rate must be positive");
+        }
+        // with no room for a token, every request would wait or be rejected forever
+        if burst == 0 {
+            bail!("burst must be positive");
+        }
+        let bucket = Bucket {
+            algorithm,
+            rate: rate as f64,
+            burst,
+            tokens: burst as f64,
+            updated: Instant::now(),
+            leaking: false,
+        };
+        Ok(RateLimiter {
+            bucket: Arc::new(Mutex::new(bucket)),
+        })
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.bucket.lock().unwrap().algorithm
+    }
+
+    pub fn tokens(&self) -> f64 {
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.refill();
+        bucket.tokens
+    }
+
+    // takes a token, or returns how long to wait for one
+    fn acquire(&self) -> Option<Duration> {
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.refill();
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return None;
+        }
+        Some(Duration::from_secs_f64((1.0 - bucket.tokens) / bucket.rate))
+    }
+}
+
+// Pulls messages from the mailbox at the pace of the limiter.
+pub(super) struct Pacer<Request, Reply> {
+    limiter: RateLimiter,
+    // the leaky bucket
+    queue: VecDeque<ActorMessage<Request, Reply>>,
+    burst: usize,
+    interval: Duration,
+    next: Instant,
+    closed: bool,
+}
+
+impl<Request, Reply> Pacer<Request, Reply> {
+    pub(super) fn new(limiter: RateLimiter) -> Result<Self> {
+        let mut bucket = limiter.bucket.lock().unwrap();
+        if bucket.algorithm == Algorithm::LeakyBucket
+            && std::mem::replace(&mut bucket.leaking, true)
+        {
+            bail!("a leaky bucket can't be shared by actors");
+        }
+        let burst = bucket.burst;
+        let interval = Duration::from_secs_f64(1.0 / bucket.rate);
+        drop(bucket);
+        Ok(Pacer {
+            limiter,
+            queue: VecDeque::new(),
+            burst,
+            interval,
+            next: Instant::now(),
+            closed: false,
+        })
+    }
+
+    pub(super) async fn recv(
+        &mut self,
+        receiver: &mut Receiver<ActorMessage<Request, Reply>>,
+    ) -> Option<ActorMessage<Request, Reply>> {
+        match self.limiter.algorithm() {
+            Algorithm::TokenBucket => {
+                let msg = receiver.recv().await?;
+                while let Some(wait) = self.limiter.acquire() {
+                    time::sleep(wait).await;
+                }
+                Some(msg)
+            }
+            Algorithm::LeakyBucket => self.leak(receiver).await,
+        }
+    }
+
+    async fn leak(
+        &mut self,
+        receiver: &mut Receiver<ActorMessage<Request, Reply>>,
+    ) -> Option<ActorMessage<Request, Reply>> {
+        loop {
+            let now = Instant::now();
+            if now >= self.next {
+                if let Some(msg) = self.queue.pop_front() {
+                    self.next = now + self.interval;
+                    self.update();
+                    return Some(msg);
+                }
+            }
+            if self.closed && self.queue.is_empty() {
+                return None;
+            }
+
+            tokio::select! {
+                msg = receiver.recv(), if !self.closed => match msg {
+                    Some(msg) if self.queue.len() < self.burst => {
+                        self.queue.push_back(msg);
+                        self.update();
+                    }
+                    Some(msg) => {
+                        let _ = msg.sender.send(Err(anyhow!("rate limit exceeded")));
+                    }
+                    None => self.closed = true,
+                },
+                _ = time::sleep_until(self.next), if !self.queue.is_empty() => {}
+            }
+        }
+    }
+
+    fn update(&self) {
+        self.limiter.bucket.lock().unwrap().tokens = (self.burst - self.queue.len()) as f64;
+    }
+}
+
+// the leaky bucket can be used by another actor once this one stops
+impl<Request, Reply> Drop for Pacer<Request, Reply> {
+    fn drop(&mut self) {
+        let mut bucket = self.limiter.bucket.lock().unwrap();
+        if bucket.algorithm == Algorithm::LeakyBucket {
+            bucket.leaking = false;
+            bucket.tokens = bucket.burst as f64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokio::actor::{spawn_rate_limited, Actor, Context};
+
+    struct Echo;
+
+    impl Actor for Echo {
+        type Request = usize;
+        type Reply = usize;
+
+        fn handle_call(&mut self, req: usize, _ctx: &mut Context<Self>) -> Result<usize> {
+            Ok(req)
+        }
+    }
+
+    #[tokio::test]
+    async fn token_bucket() {
+        time::pause();
+        let limiter = RateLimiter::token_bucket(50, 3).unwrap();
+        let pid = spawn_rate_limited(Echo, 20, limiter.clone()).unwrap();
+        assert_eq!(limiter.tokens(), 3.0);
+
+        let start = Instant::now();
+        for n in 0..3 {
+            assert_eq!(pid.send(n).await.unwrap(), n);
+        }
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        assert!(limiter.tokens() < 1.0);
+
+        // a token every 20ms after the burst
+        for n in 0..3 {
+            assert_eq!(pid.send(n).await.unwrap(), n);
+        }
+        // timers have millisecond resolution, so each wait may end a bit later
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(60) && elapsed <= Duration::from_millis(63));
+
+        time::advance(Duration::from_millis(100)).await;
+        assert_eq!(limiter.tokens(), 3.0);
+    }
+
+    #[tokio::test]
+    async fn leaky_bucket() {
+        time::pause();
+        let limiter = RateLimiter::leaky_bucket(50, 2).unwrap();
+        let pid = spawn_rate_limited(Echo, 20, limiter.clone()).unwrap();
+        let requests: Vec<_> = (0..5)
+            .map(|n| {
+                let pid = pid.clone();
+                tokio::spawn(async move { pid.send(n).await })
+            })
+            .collect();
+        time::advance(Duration::from_millis(5)).await;
+        // one request is handled right away, two wait in the bucket
+        assert_eq!(limiter.tokens(), 0.0);
+        let start = Instant::now();
+
+        let mut handled = 0;
+        let mut rejected = 0;
+        for request in requests {
+            match request.await.unwrap() {
+                Ok(_) => handled += 1,
+                Err(e) => {
+                    assert_eq!(e.to_string(), "rate limit exceeded");
+                    rejected += 1;
+                }
+            }
+        }
+        assert_eq!((handled, rejected), (3, 2));
+        // the last one leaks 2 intervals after the first
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(40) && elapsed <= Duration::from_millis(42));
+        assert_eq!(limiter.tokens(), 2.0);
+    }
+
+    #[tokio::test]
+    async fn leaky_bucket_is_not_shared() {
+        time::pause();
+        let limiter = RateLimiter::leaky_bucket(50, 2).unwrap();
+        let pid = spawn_rate_limited(Echo, 20, limiter.clone()).unwrap();
+        let err = spawn_rate_limited(Echo, 20, limiter.clone()).unwrap_err();
+        assert_eq!(err.to_string(), "a leaky bucket can't be shared by actors");
+        // the failed spawn doesn't free it
+        assert!(spawn_rate_limited(Echo, 20, limiter.clone()).is_err());
+
+        // free again once the actor stops
+        drop(pid);
+        time::sleep(Duration::from_millis(5)).await;
+        spawn_rate_limited(Echo, 20, limiter).unwrap();
+    }
+
+    #[test]
+    fn rate_and_burst_must_be_positive() {
+        let err = RateLimiter::token_bucket(0, 3).err().unwrap();
+        assert_eq!(err.to_string(), "rate must be positive");
+        let err = RateLimiter::leaky_bucket(50, 0).err().unwrap();
+        assert_eq!(err.to_string(), "burst must be positive");
+    }
+}
@@ -0,0 +1,296 @@
+// Derived watch channels, like Rx operators: https://reactivex.io/documentation/operators.html
+// Every operator spawns a task which forwards values until the upstream sender is dropped or all
+// downstream receivers are, so dropping either end shuts the chain down.
+use std::time::Duration;
+
+use tokio::{
+    sync::watch::{self, Receiver, Sender},
+    time,
+};
+
+pub trait WatchExt<T> {
+    fn map<U, F>(self, f: F) -> Receiver<U>
+    where
+        U: Send + Sync + 'static,
+        F: Fn(&T) -> U + Send + 'static;
+
+    // the current value is kept as is, later values are forwarded only if they pass
+    fn filter<F>(self, predicate: F) -> Receiver<T>
+    where
+        T: Clone,
+        F: Fn(&T) -> bool + Send + 'static;
+
+    // drops values equal to the last one
+    fn dedupe(self) -> Receiver<T>
+    where
+        T: Clone + PartialEq;
+
+    // forwards the latest value once it hasn't changed for `period`
+    fn debounce(self, period: Duration) -> Receiver<T>
+    where
+        T: Clone;
+
+    // forwards at most one value per `period`, the first right away and the latest at the end
+    fn throttle(self, period: Duration) -> Receiver<T>
+    where
+        T: Clone;
+}
+
+impl<T: Send + Sync + 'static> WatchExt<T> for Receiver<T> {
+    fn map<U, F>(mut self, f: F) -> Receiver<U>
+    where
+        U: Send + Sync + 'static,
+        F: Fn(&T) -> U + Send + 'static,
+    {
+        let (tx, rx) = watch::channel(f(&self.borrow()));
+        tokio::spawn(async move {
+            while changed(&mut self, &tx).await {
+                let value = f(&self.borrow());
+                if tx.send(value).is_err() {
+                    break;
+                }
+            }
+        });
+        rx
+    }
+
+    fn filter<F>(self, predicate: F) -> Receiver<T>
+    where
+        T: Clone,
+        F: Fn(&T) -> bool + Send + 'static,
+    {
+        filter_last(self, move |value, _| predicate(value))
+    }
+
+    fn dedupe(self) -> Receiver<T>
+    where
+        T: Clone + PartialEq,
+    {
+        filter_last(self, |value, last| value != last)
+    }
+
+    fn debounce(mut self, period: Duration) -> Receiver<T>
+    where
+        T: Clone,
+    {
+        let (tx, rx) = watch::channel(self.borrow().clone());
+        tokio::spawn(async move {
+            while changed(&mut self, &tx).await {
+                let open = loop {
+                    tokio::select! {
+                        result = self.changed() => if result.is_err() {
+                            break false;
+                        },
+                        _ = time::sleep(period) => break true,
+                    }
+                };
+                let value = self.borrow().clone();
+                if tx.send(value).is_err() || !open {
+                    break;
+                }
+            }
+        });
+        rx
+    }
+
+    fn throttle(mut self, period: Duration) -> Receiver<T>
+    where
+        T: Clone,
+    {
+        let (tx, rx) = watch::channel(self.borrow().clone());
+        tokio::spawn(async move {
+            // values sent while sleeping are seen as one change after it
+            while changed(&mut self, &tx).await {
+                let value = self.borrow().clone();
+                if tx.send(value).is_err() {
+                    break;
+                }
+                time::sleep(period).await;
+            }
+        });
+        rx
+    }
+}
+
+// the latest values of both, closed when both upstream senders are dropped
+pub fn combine_latest<A, B>(mut a: Receiver<A>, mut b: Receiver<B>) -> Receiver<(A, B)>
+where
+    A: Clone + Send + Sync + 'static,
+    B: Clone + Send + Sync + 'static,
+{
+    let (tx, rx) = watch::channel((a.borrow().clone(), b.borrow().clone()));
+    tokio::spawn(async move {
+        let (mut a_open, mut b_open) = (true, true);
+        while a_open || b_open {
+            let changed = tokio::select! {
+                result = a.changed(), if a_open => {
+                    a_open = result.is_ok();
+                    a_open
+                }
+                result = b.changed(), if b_open => {
+                    b_open = result.is_ok();
+                    b_open
+                }
+                _ = tx.closed() => break,
+            };
+            if changed {
+                let value = (a.borrow().clone(), b.borrow().clone());
+                if tx.send(value).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+    rx
+}
+
+// false when the upstream sender or all downstream receivers are dropped
+async fn changed<T, U>(rx: &mut Receiver<T>, tx: &Sender<U>) -> bool {
+    tokio::select! {
+        result = rx.changed() => result.is_ok(),
+        _ = tx.closed() => false,
+    }
+}
+
+// forwards the values `predicate` accepts, it also gets the last forwarded value
+fn filter_last<T, F>(mut rx: Receiver<T>, predicate: F) -> Receiver<T>
+where
+    T: Clone + Send + Sync + 'static,
+    F: Fn(&T, &T) -> bool + Send + 'static,
+{
+    let (tx, out) = watch::channel(rx.borrow().clone());
+    tokio::spawn(async move {
+        while changed(&mut rx, &tx).await {
+            let value = {
+                let value = rx.borrow();
+                predicate(&value, &tx.borrow()).then(|| value.clone())
+            };
+            if let Some(value) = value {
+                if tx.send(value).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::Instant;
+
+    async fn send<T>(tx: &Sender<T>, value: T) {
+        tx.send(value).ok().unwrap();
+        // let the operators see every value, watch channels only keep the latest
+        time::sleep(Duration::from_millis(2)).await;
+    }
+
+    async fn unchanged<T>(rx: &mut Receiver<T>) -> bool {
+        time::timeout(Duration::from_millis(10), rx.changed())
+            .await
+            .is_err()
+    }
+
+    #[tokio::test]
+    async fn map_and_filter() {
+        let (tx, rx) = watch::channel(1);
+        let mut doubled = rx.clone().map(|v| v * 2);
+        let mut even = rx.filter(|v| v % 2 == 0);
+        assert_eq!(*doubled.borrow(), 2);
+
+        send(&tx, 3).await;
+        doubled.changed().await.unwrap();
+        assert_eq!(*doubled.borrow(), 6);
+        assert!(unchanged(&mut even).await);
+
+        send(&tx, 4).await;
+        even.changed().await.unwrap();
+        assert_eq!(*even.borrow(), 4);
+
+        drop(tx);
+        doubled.changed().await.unwrap();
+        assert!(doubled.changed().await.is_err());
+        assert!(even.changed().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn dedupe() {
+        let (tx, rx) = watch::channel(1);
+        let mut rx = rx.dedupe();
+        send(&tx, 1).await;
+        assert!(unchanged(&mut rx).await);
+        send(&tx, 2).await;
+        rx.changed().await.unwrap();
+        assert_eq!(*rx.borrow(), 2);
+        send(&tx, 2).await;
+        assert!(unchanged(&mut rx).await);
+    }
+
+    #[tokio::test]
+    async fn debounce() {
+        let (tx, rx) = watch::channel(0);
+        let mut rx = rx.debounce(Duration::from_millis(20));
+        let start = Instant::now();
+        for n in 1..=3 {
+            send(&tx, n).await;
+        }
+        rx.changed().await.unwrap();
+        assert_eq!(*rx.borrow(), 3);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        // the pending value is forwarded before closing
+        send(&tx, 4).await;
+        drop(tx);
+        rx.changed().await.unwrap();
+        assert_eq!(*rx.borrow(), 4);
+        assert!(rx.changed().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn throttle() {
+        let (tx, rx) = watch::channel(0);
+        let mut rx = rx.throttle(Duration::from_millis(30));
+        let start = Instant::now();
+        send(&tx, 1).await;
+        rx.changed().await.unwrap();
+        assert_eq!(*rx.borrow(), 1);
+        assert!(start.elapsed() < Duration::from_millis(30));
+
+        send(&tx, 2).await;
+        send(&tx, 3).await;
+        rx.changed().await.unwrap();
+        assert_eq!(*rx.borrow(), 3);
+        assert!(start.elapsed() >= Duration::from_millis(30));
+    }
+
+    #[tokio::test]
+    async fn combine() {
+        let (tx_a, a) = watch::channel(1);
+        let (tx_b, b) = watch::channel("x");
+        let mut rx = combine_latest(a, b);
+        assert_eq!(*rx.borrow(), (1, "x"));
+
+        send(&tx_a, 2).await;
+        rx.changed().await.unwrap();
+        assert_eq!(*rx.borrow(), (2, "x"));
+
+        // still open while one of them is
+        drop(tx_a);
+        send(&tx_b, "y").await;
+        rx.changed().await.unwrap();
+        assert_eq!(*rx.borrow(), (2, "y"));
+        drop(tx_b);
+        assert!(rx.changed().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn downstream_drop_stops_the_chain() {
+        let (tx, rx) = watch::channel(0);
+        let out = rx.map(|v| v + 1).dedupe();
+        assert!(!tx.is_closed());
+        drop(out);
+        time::sleep(Duration::from_millis(5)).await;
+        assert!(tx.is_closed());
+    }
+}
@@ -0,0 +1,362 @@
+// Circuit breaker: https://martinfowler.com/bliki/CircuitBreaker.html
+//  - Closed: requests go through, consecutive failures are counted
+//  - Open: requests are rejected right away until `open_timeout` passes
+//  - HalfOpen: one probe request at a time, enough successes close the breaker, a failure opens it again
+// Retries use exponential backoff with jitter: https://aws.amazon.com/blogs/architecture/exponential-backoff-and-jitter/
+// Handler errors count as failures too, since `Pid::send` can't tell them apart from a dead actor.
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use tokio::time::{self, Instant};
+
+use super::actor::Pid;
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    // consecutive failures to open the breaker
+    pub failure_threshold: usize,
+    // successes in half-open state to close the breaker
+    pub success_threshold: usize,
+    pub open_timeout: Duration,
+    pub call_timeout: Option<Duration>,
+    pub max_retries: usize,
+    pub base_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            failure_threshold: 5,
+            success_threshold: 1,
+            open_timeout: Duration::from_secs(10),
+            call_timeout: Some(Duration::from_secs(5)),
+            max_retries: 3,
+            base_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metrics {
+    pub successes: usize,
+    pub failures: usize,
+    // requests rejected without reaching the actor
+    pub rejected: usize,
+    pub retries: usize,
+    // times the breaker went to open state
+    pub trips: usize,
+}
+
+struct Breaker {
+    state: State,
+    failures: usize,
+    successes: usize,
+    opened_at: Instant,
+    // when the half-open probe started, a probe whose caller gave up is stale after `open_timeout`
+    probing: Option<Instant>,
+    metrics: Metrics,
+}
+
+impl Breaker {
+    fn acquire(&mut self, config: &Config) -> Result<()> {
+        if self.state == State::Open && self.opened_at.elapsed() >= config.open_timeout {
+            self.state = State::HalfOpen;
+            self.successes = 0;
+        }
+
+        match self.state {
+            State::Closed => Ok(()),
+            State::HalfOpen
+                if self
+                    .probing
+                    .is_none_or(|at| at.elapsed() >= config.open_timeout) =>
+            {
+                self.probing = Some(Instant::now());
+                Ok(())
+            }
+            _ => {
+                self.metrics.rejected += 1;
+                Err(anyhow!("circuit breaker is open"))
+            }
+        }
+    }
+
+    fn on_success(&mut self, config: &Config) {
+        self.metrics.successes += 1;
+        self.failures = 0;
+        if self.state == State::HalfOpen {
+            self.probing = None;
+            self.successes += 1;
+            if self.successes >= config.success_threshold {
+                self.state = State::Closed;
+            }
+        }
+    }
+
+    fn on_failure(&mut self, config: &Config) {
+        self.metrics.failures += 1;
+        self.failures += 1;
+        let trip = match self.state {
+            State::HalfOpen => true,
+            State::Closed => self.failures >= config.failure_threshold,
+            State::Open => false,
+        };
+        self.probing = None;
+        if trip {
+            self.state = State::Open;
+            self.opened_at = Instant::now();
+            self.metrics.trips += 1;
+        }
+    }
+}
+
+pub struct ResilientPid<Request, Reply> {
+    pid: Pid<Request, Reply>,
+    config: Config,
+    // shared by clones, so all callers see the same breaker
+    breaker: Arc<Mutex<Breaker>>,
+}
+
+impl<Request, Reply> Clone for ResilientPid<Request, Reply> {
+    fn clone(&self) -> Self {
+        ResilientPid {
+            pid: self.pid.clone(),
+            config: self.config.clone(),
+            breaker: self.breaker.clone(),
+        }
+    }
+}
+
+impl<Request, Reply> ResilientPid<Request, Reply> {
+    pub fn new(pid: Pid<Request, Reply>, config: Config) -> Self {
+        let breaker = Breaker {
+            state: State::Closed,
+            failures: 0,
+            successes: 0,
+            opened_at: Instant::now(),
+            probing: None,
+            metrics: Metrics::default(),
+        };
+        ResilientPid {
+            pid,
+            config,
+            breaker: Arc::new(Mutex::new(breaker)),
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.breaker.lock().unwrap().state
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        self.breaker.lock().unwrap().metrics.clone()
+    }
+
+    // sends once through the breaker, without retry
+    pub async fn send(&self, data: Request) -> Result<Reply> {
+        self.call(None, data).await
+    }
+
+    async fn call(&self, id: Option<u64>, data: Request) -> Result<Reply> {
+        self.breaker.lock().unwrap().acquire(&self.config)?;
+
+        let send = async {
+            match id {
+                Some(id) => self.pid.send_with_id(id, data).await,
+                None => self.pid.send(data).await,
+            }
+        };
+        let result = match self.config.call_timeout {
+            Some(timeout) => match time::timeout(timeout, send).await {
+                Ok(result) => result,
+                Err(_) => Err(anyhow!("request timed out")),
+            },
+            None => send.await,
+        };
+
+        let mut breaker = self.breaker.lock().unwrap();
+        match result {
+            Ok(_) => breaker.on_success(&self.config),
+            Err(_) => breaker.on_failure(&self.config),
+        }
+        result
+    }
+
+    // Only for idempotent requests, the actor may handle the request more than once.
+    // Every attempt has the same request id, so an actor from `spawn_idempotent` handles it once.
+    pub async fn send_idempotent(&self, data: Request) -> Result<Reply>
+    where
+        Request: Clone,
+    {
+        let id = RandomState::new().build_hasher().finish();
+        let mut attempt = 0;
+        loop {
+            match self.call(Some(id), data.clone()).await {
+                Ok(reply) => return Ok(reply),
+                Err(e) if attempt >= self.config.max_retries || self.state() == State::Open => {
+                    return Err(e)
+                }
+                Err(_) => {
+                    time::sleep(self.backoff(attempt)).await;
+                    attempt += 1;
+                    self.breaker.lock().unwrap().metrics.retries += 1;
+                }
+            }
+        }
+    }
+
+    // equal jitter: half of the exponential backoff plus a random part of the other half
+    fn backoff(&self, attempt: usize) -> Duration {
+        let exp = self
+            .config
+            .base_backoff
+            .checked_mul(1 << attempt.min(31))
+            .unwrap_or(self.config.max_backoff)
+            .min(self.config.max_backoff);
+        let half = exp / 2;
+        let random = RandomState::new().build_hasher().finish();
+        half + half.mul_f64((random % 1000) as f64 / 1000.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokio::actor::{spawn, Actor, Context};
+
+    // fails the first `failures` requests
+    struct Flaky {
+        failures: usize,
+        handled: usize,
+    }
+
+    impl Actor for Flaky {
+        type Request = usize;
+        type Reply = usize;
+
+        fn handle_call(&mut self, req: usize, _ctx: &mut Context<Self>) -> Result<usize> {
+            self.handled += 1;
+            if self.handled <= self.failures {
+                return Err(anyhow!("overloaded"));
+            }
+            Ok(req + 1)
+        }
+    }
+
+    fn flaky(failures: usize, config: Config) -> ResilientPid<usize, usize> {
+        let pid = spawn(
+            Flaky {
+                failures,
+                handled: 0,
+            },
+            20,
+        );
+        ResilientPid::new(pid, config)
+    }
+
+    fn config() -> Config {
+        Config {
+            failure_threshold: 3,
+            success_threshold: 2,
+            open_timeout: Duration::from_millis(20),
+            base_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(4),
+            ..Config::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn retry_until_success() {
+        let pid = flaky(2, config());
+        assert_eq!(pid.send_idempotent(1).await.unwrap(), 2);
+        let metrics = pid.metrics();
+        assert_eq!(metrics.retries, 2);
+        assert_eq!(metrics.failures, 2);
+        assert_eq!(metrics.successes, 1);
+        assert_eq!(pid.state(), State::Closed);
+    }
+
+    #[tokio::test]
+    async fn give_up_after_max_retries() {
+        let pid = flaky(
+            100,
+            Config {
+                failure_threshold: 100,
+                max_retries: 2,
+                ..config()
+            },
+        );
+        assert!(pid.send_idempotent(1).await.is_err());
+        assert_eq!(pid.metrics().failures, 3);
+        assert_eq!(pid.metrics().retries, 2);
+    }
+
+    #[tokio::test]
+    async fn open_and_close() {
+        let pid = flaky(4, config());
+        for _ in 0..3 {
+            assert!(pid.send(1).await.is_err());
+        }
+        assert_eq!(pid.state(), State::Open);
+        let err = pid.send(1).await.unwrap_err();
+        assert_eq!(err.to_string(), "circuit breaker is open");
+        // retries stop as soon as the breaker is open
+        assert!(pid.send_idempotent(1).await.is_err());
+        assert_eq!(pid.metrics().rejected, 2);
+
+        // half open, the probe fails and the breaker opens again
+        time::sleep(Duration::from_millis(30)).await;
+        assert!(pid.send(1).await.is_err());
+        assert_eq!(pid.state(), State::Open);
+        assert_eq!(pid.metrics().trips, 2);
+
+        time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(pid.send(1).await.unwrap(), 2);
+        assert_eq!(pid.state(), State::HalfOpen);
+        assert_eq!(pid.send(2).await.unwrap(), 3);
+        assert_eq!(pid.state(), State::Closed);
+    }
+
+    #[tokio::test]
+    async fn call_timeout_is_failure() {
+        // the mailbox is never drained, so no reply comes back
+        let (sender, _receiver) = tokio::sync::mpsc::channel(1);
+        let config = Config {
+            failure_threshold: 1,
+            call_timeout: Some(Duration::from_millis(5)),
+            ..config()
+        };
+        let pid: ResilientPid<(), ()> = ResilientPid::new(Pid { sender }, config);
+        let err = pid.send(()).await.unwrap_err();
+        assert_eq!(err.to_string(), "request timed out");
+        assert_eq!(pid.state(), State::Open);
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        let pid: ResilientPid<(), ()> = ResilientPid::new(
+            Pid {
+                sender: tokio::sync::mpsc::channel(1).0,
+            },
+            config(),
+        );
+        assert!(pid.backoff(0) <= Duration::from_millis(1));
+        assert!(pid.backoff(0) >= Duration::from_micros(500));
+        assert!(pid.backoff(40) <= Duration::from_millis(4));
+        assert!(pid.backoff(40) >= Duration::from_millis(2));
+    }
+}
@@ -0,0 +1,507 @@
+// Saga: https://microservices.io/patterns/data/saga.html
+// Stages run one after another, the steps in a stage run in parallel. When a step fails, every
+// step done so far is compensated in reverse order.
+// Every step is logged before and after it runs. After a crash, `recover` compensates the steps
+// a saga started, so compensations must be idempotent.
+use std::{fmt, future::Future, path::PathBuf, pin::Pin, sync::Arc};
+
+use anyhow::{anyhow, Result};
+use tokio::{
+    fs::{self, OpenOptions},
+    io::AsyncWriteExt,
+    sync::Mutex,
+};
+
+use super::actor::Pid;
+
+// A step failed and compensating the steps done so far failed too.
+#[derive(Debug)]
+pub struct CompensationFailed {
+    pub step: anyhow::Error,
+    pub compensation: anyhow::Error,
+}
+
+impl fmt::Display for CompensationFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:#} (compensation failed: {:#})",
+            self.step, self.compensation
+        )
+    }
+}
+
+impl std::error::Error for CompensationFailed {}
+
+type Action = Arc<dyn Fn() -> Pin<Box<dyn Future<Output = Result<()>> + Send>> + Send + Sync>;
+
+#[derive(Clone)]
+pub struct Step {
+    name: String,
+    action: Action,
+    compensation: Action,
+}
+
+impl Step {
+    pub fn new<A, AF, C, CF>(name: &str, action: A, compensation: C) -> Self
+    where
+        A: Fn() -> AF + Send + Sync + 'static,
+        AF: Future<Output = Result<()>> + Send + 'static,
+        C: Fn() -> CF + Send + Sync + 'static,
+        CF: Future<Output = Result<()>> + Send + 'static,
+    {
+        Step {
+            name: name.to_string(),
+            action: Arc::new(move || Box::pin(action())),
+            compensation: Arc::new(move || Box::pin(compensation())),
+        }
+    }
+
+    // sends `request` to the actor, and `compensation` to undo it
+    pub fn call<Request, Reply>(
+        name: &str,
+        pid: Pid<Request, Reply>,
+        request: Request,
+        compensation: Request,
+    ) -> Self
+    where
+        Request: Clone + Send + Sync + 'static,
+        Reply: Send + 'static,
+    {
+        let compensate = pid.clone();
+        Step::new(
+            name,
+            move || {
+                let pid = pid.clone();
+                let request = request.clone();
+                async move { pid.send(request).await.map(|_| ()) }
+            },
+            move || {
+                let pid = compensate.clone();
+                let compensation = compensation.clone();
+                async move { pid.send(compensation).await.map(|_| ()) }
+            },
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Event {
+    Started(String),
+    Done(String),
+    Compensated(String),
+    Completed,
+    Aborted,
+}
+
+// Append-only file, one `<saga id>\t<event>\t<step>` line per event.
+pub struct SagaLog {
+    path: PathBuf,
+    lock: Mutex<()>,
+    // appending a line which contains it fails
+    #[cfg(test)]
+    fail: std::sync::Mutex<Option<String>>,
+}
+
+impl SagaLog {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        SagaLog {
+            path: path.into(),
+            lock: Mutex::new(()),
+            #[cfg(test)]
+            fail: std::sync::Mutex::new(None),
+        }
+    }
+
+    async fn append(&self, id: &str, event: &Event) -> Result<()> {
+        let line = match event {
+            Event::Started(step) => format!("{}\tstarted\t{}\n", id, step),
+            Event::Done(step) => format!("{}\tdone\t{}\n", id, step),
+            Event::Compensated(step) => format!("{}\tcompensated\t{}\n", id, step),
+            Event::Completed => format!("{}\tcompleted\t\n", id),
+            Event::Aborted => format!("{}\taborted\t\n", id),
+        };
+        #[cfg(test)]
+        if let Some(fail) = self.fail.lock().unwrap().as_deref() {
+            if line.contains(fail) {
+                return Err(anyhow!("can't append to the saga log"));
+            }
+        }
+
+        let _guard = self.lock.lock().await;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(line.as_bytes()).await?;
+        file.sync_data().await?;
+        Ok(())
+    }
+
+    async fn events(&self, id: &str) -> Result<Vec<Event>> {
+        let content = match fs::read_to_string(&self.path).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut events = Vec::new();
+        for line in content.lines() {
+            let mut fields = line.splitn(3, '\t');
+            if fields.next() != Some(id) {
+                continue;
+            }
+            let kind = fields.next().unwrap_or_default();
+            let step = fields.next().unwrap_or_default().to_string();
+            events.push(match kind {
+                "started" => Event::Started(step),
+                "done" => Event::Done(step),
+                "compensated" => Event::Compensated(step),
+                "completed" => Event::Completed,
+                "aborted" => Event::Aborted,
+                _ => return Err(anyhow!("invalid saga log line: {}", line)),
+            });
+        }
+        Ok(events)
+    }
+
+    // ids of sagas which neither completed nor aborted
+    pub async fn unfinished(&self) -> Result<Vec<String>> {
+        let content = match fs::read_to_string(&self.path).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut ids: Vec<String> = Vec::new();
+        for line in content.lines() {
+            let mut fields = line.splitn(3, '\t');
+            let id = fields.next().unwrap_or_default();
+            match fields.next() {
+                Some("completed") | Some("aborted") => ids.retain(|i| i != id),
+                _ if !ids.iter().any(|i| i == id) => ids.push(id.to_string()),
+                _ => {}
+            }
+        }
+        Ok(ids)
+    }
+}
+
+pub struct Saga {
+    id: String,
+    stages: Vec<Vec<Step>>,
+}
+
+impl Saga {
+    // the id must be unique in the log and can't contain tabs or newlines
+    pub fn new(id: &str) -> Self {
+        Saga {
+            id: id.to_string(),
+            stages: Vec::new(),
+        }
+    }
+
+    pub fn step(mut self, step: Step) -> Self {
+        self.stages.push(vec![step]);
+        self
+    }
+
+    pub fn parallel(mut self, steps: Vec<Step>) -> Self {
+        self.stages.push(steps);
+        self
+    }
+
+    // Returns the error of the failed step after compensating. A step which is done but can't
+    // be logged as done fails the saga too, the log error is returned after compensating. When
+    // compensating fails too, both errors are returned in a `CompensationFailed`.
+    pub async fn run(&self, log: &SagaLog) -> Result<()> {
+        let mut done: Vec<&Step> = Vec::new();
+        for stage in self.stages.iter() {
+            for step in stage.iter() {
+                if let Err(e) = log
+                    .append(&self.id, &Event::Started(step.name.clone()))
+                    .await
+                {
+                    return Err(self.abort(log, done, e).await);
+                }
+            }
+
+            let handles: Vec<_> = stage
+                .iter()
+                .map(|step| tokio::spawn((step.action)()))
+                .collect();
+            let mut failure = None;
+            for (step, handle) in stage.iter().zip(handles) {
+                match handle.await.map_err(|e| e.into()).and_then(|r| r) {
+                    Ok(()) => {
+                        // the other steps of the stage are awaited before compensating
+                        done.push(step);
+                        if let Err(e) = log.append(&self.id, &Event::Done(step.name.clone())).await
+                        {
+                            failure = failure.or(Some(e));
+                        }
+                    }
+                    Err(e) => failure = failure.or(Some(e)),
+                }
+            }
+
+            if let Some(e) = failure {
+                return Err(self.abort(log, done, e).await);
+            }
+        }
+
+        log.append(&self.id, &Event::Completed).await
+    }
+
+    // compensates the steps started by an unfinished run, in reverse order
+    pub async fn recover(&self, log: &SagaLog) -> Result<()> {
+        let events = log.events(&self.id).await?;
+        if events.is_empty()
+            || events.contains(&Event::Completed)
+            || events.contains(&Event::Aborted)
+        {
+            return Ok(());
+        }
+
+        let mut started = Vec::new();
+        for event in events.iter() {
+            match event {
+                Event::Started(name) if !started.contains(&name) => started.push(name),
+                Event::Compensated(name) => started.retain(|&n| n != name),
+                _ => {}
+            }
+        }
+
+        let steps = started
+            .into_iter()
+            .map(|name| {
+                self.stages
+                    .iter()
+                    .flatten()
+                    .find(|step| &step.name == name)
+                    .ok_or_else(|| anyhow!("unknown step in saga log: {}", name))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.compensate(log, steps).await
+    }
+
+    // compensates the done steps after the run failed with `e`, returns the error of the run
+    async fn abort(&self, log: &SagaLog, steps: Vec<&Step>, e: anyhow::Error) -> anyhow::Error {
+        match self.compensate(log, steps).await {
+            Ok(()) => e,
+            Err(c) => anyhow::Error::new(CompensationFailed {
+                step: e,
+                compensation: c,
+            }),
+        }
+    }
+
+    // stops at the first failed compensation, so the saga stays unfinished for `recover`
+    async fn compensate(&self, log: &SagaLog, steps: Vec<&Step>) -> Result<()> {
+        for step in steps.into_iter().rev() {
+            (step.compensation)().await?;
+            log.append(&self.id, &Event::Compensated(step.name.clone()))
+                .await?;
+        }
+        log.append(&self.id, &Event::Aborted).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex as StdMutex;
+
+    use super::*;
+    use crate::tokio::actor::{spawn, Actor, Context};
+
+    #[derive(Debug, Clone)]
+    enum Request {
+        Do(&'static str),
+        Undo(&'static str),
+    }
+
+    // records every request, and fails `Do` of the named step
+    struct Service {
+        calls: Arc<StdMutex<Vec<String>>>,
+        fail: Option<&'static str>,
+    }
+
+    impl Actor for Service {
+        type Request = Request;
+        type Reply = ();
+
+        fn handle_call(&mut self, req: Request, _ctx: &mut Context<Self>) -> Result<()> {
+            match req {
+                Request::Do(name) if Some(name) == self.fail => Err(anyhow!("{} failed", name)),
+                Request::Do(name) => {
+                    self.calls.lock().unwrap().push(format!("do {}", name));
+                    Ok(())
+                }
+                Request::Undo(name) => {
+                    self.calls.lock().unwrap().push(format!("undo {}", name));
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    fn service(fail: Option<&'static str>) -> (Pid<Request, ()>, Arc<StdMutex<Vec<String>>>) {
+        let calls = Arc::new(StdMutex::new(Vec::new()));
+        let pid = spawn(
+            Service {
+                calls: calls.clone(),
+                fail,
+            },
+            20,
+        );
+        (pid, calls)
+    }
+
+    fn step(pid: &Pid<Request, ()>, name: &'static str) -> Step {
+        Step::call(name, pid.clone(), Request::Do(name), Request::Undo(name))
+    }
+
+    fn log(name: &str) -> SagaLog {
+        let path = std::env::temp_dir().join(format!("saga-{}-{}.log", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        SagaLog::new(path)
+    }
+
+    #[tokio::test]
+    async fn completed() {
+        let log = log("completed");
+        let (pid, calls) = service(None);
+        let saga = Saga::new("order-1")
+            .step(step(&pid, "reserve"))
+            .step(step(&pid, "charge"));
+        saga.run(&log).await.unwrap();
+
+        assert_eq!(*calls.lock().unwrap(), vec!["do reserve", "do charge"]);
+        assert!(log.unfinished().await.unwrap().is_empty());
+        std::fs::remove_file(&log.path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn compensate_in_reverse_order() {
+        let log = log("compensate");
+        let (pid, calls) = service(Some("ship"));
+        let saga = Saga::new("order-1")
+            .step(step(&pid, "reserve"))
+            .step(step(&pid, "charge"))
+            .step(step(&pid, "ship"))
+            .step(step(&pid, "notify"));
+        let err = saga.run(&log).await.unwrap_err();
+
+        assert_eq!(err.to_string(), "ship failed");
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec!["do reserve", "do charge", "undo charge", "undo reserve"]
+        );
+        assert!(log.unfinished().await.unwrap().is_empty());
+        std::fs::remove_file(&log.path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn compensation_failure_keeps_step_error() {
+        let log = log("compensation-failure");
+        let (failing, _) = service(Some("ship"));
+        let charge = Step::new(
+            "charge",
+            || async { Ok(()) },
+            || async { Err(anyhow!("refund failed")) },
+        );
+        let saga = Saga::new("order-1")
+            .step(charge)
+            .step(step(&failing, "ship"));
+        let err = saga.run(&log).await.unwrap_err();
+
+        let err = err.downcast::<CompensationFailed>().unwrap();
+        assert_eq!(err.step.to_string(), "ship failed");
+        assert_eq!(err.compensation.to_string(), "refund failed");
+        // not aborted, so it can be recovered
+        assert_eq!(log.unfinished().await.unwrap(), vec!["order-1"]);
+        std::fs::remove_file(&log.path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn parallel_stage() {
+        let log = log("parallel");
+        let (pid, calls) = service(None);
+        let (failing, failing_calls) = service(Some("b"));
+        let saga = Saga::new("order-1")
+            .step(step(&pid, "first"))
+            .parallel(vec![step(&pid, "a"), step(&failing, "b"), step(&pid, "c")]);
+        assert!(saga.run(&log).await.is_err());
+
+        let mut calls = calls.lock().unwrap().clone();
+        assert_eq!(calls.remove(0), "do first");
+        assert_eq!(calls.pop().unwrap(), "undo first");
+        calls.sort();
+        assert_eq!(calls, vec!["do a", "do c", "undo a", "undo c"]);
+        assert!(failing_calls.lock().unwrap().is_empty());
+        std::fs::remove_file(&log.path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn recover_unfinished() {
+        let log = log("recover");
+        // a run which crashed while charging
+        log.append("order-1", &Event::Started("reserve".to_string()))
+            .await
+            .unwrap();
+        log.append("order-1", &Event::Done("reserve".to_string()))
+            .await
+            .unwrap();
+        log.append("order-1", &Event::Started("charge".to_string()))
+            .await
+            .unwrap();
+        log.append("order-2", &Event::Completed).await.unwrap();
+        assert_eq!(log.unfinished().await.unwrap(), vec!["order-1"]);
+
+        let (pid, calls) = service(None);
+        let saga = Saga::new("order-1")
+            .step(step(&pid, "reserve"))
+            .step(step(&pid, "charge"))
+            .step(step(&pid, "ship"));
+        saga.recover(&log).await.unwrap();
+
+        assert_eq!(*calls.lock().unwrap(), vec!["undo charge", "undo reserve"]);
+        assert!(log.unfinished().await.unwrap().is_empty());
+        // recovering again does nothing
+        saga.recover(&log).await.unwrap();
+        assert_eq!(calls.lock().unwrap().len(), 2);
+        std::fs::remove_file(&log.path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn log_failure_compensates() {
+        let log = log("log-failure");
+        let (pid, calls) = service(None);
+        // `charge` is done, then its done event can't be logged
+        *log.fail.lock().unwrap() = Some("done\tcharge".to_string());
+        let saga = Saga::new("order-1")
+            .step(step(&pid, "reserve"))
+            .step(step(&pid, "charge"))
+            .step(step(&pid, "ship"));
+        let err = saga.run(&log).await.unwrap_err();
+
+        assert_eq!(err.to_string(), "can't append to the saga log");
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec!["do reserve", "do charge", "undo charge", "undo reserve"]
+        );
+        assert_eq!(
+            log.events("order-1").await.unwrap(),
+            vec![
+                Event::Started("reserve".to_string()),
+                Event::Done("reserve".to_string()),
+                Event::Started("charge".to_string()),
+                Event::Compensated("charge".to_string()),
+                Event::Compensated("reserve".to_string()),
+                Event::Aborted,
+            ]
+        );
+        std::fs::remove_file(&log.path).unwrap();
+    }
+}
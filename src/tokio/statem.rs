@@ -0,0 +1,344 @@
+// gen_statem style actor: https://www.erlang.org/doc/design_principles/statem.html
+//
+// Every state gets its own handler instead of one big `match self.state` in `handle_call`.
+// A handler returns a `Transition` which can:
+//  - keep the state or go to the next one (enter handler of the next state is called)
+//  - reply to the caller
+//  - postpone the request, it's re-delivered after the next state change
+//  - arm a state timeout, it's cancelled when the state changes
+// A handler error is the reply to the caller, so is an enter handler error after a transition.
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Debug,
+    hash::Hash,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use tokio::{
+    sync::{mpsc, mpsc::Receiver},
+    time::{self, Instant},
+};
+use tracing::{info_span, Span};
+
+use super::actor::{ActorMessage, Pid};
+
+#[derive(Debug, PartialEq)]
+pub enum Event<Request> {
+    Call(Request),
+    StateTimeout,
+}
+
+#[derive(Debug)]
+pub struct Transition<State, Reply> {
+    next: Option<State>,
+    reply: Option<Reply>,
+    postpone: bool,
+    state_timeout: Option<Duration>,
+}
+
+impl<State, Reply> Transition<State, Reply> {
+    pub fn keep() -> Self {
+        Transition {
+            next: None,
+            reply: None,
+            postpone: false,
+            state_timeout: None,
+        }
+    }
+
+    pub fn next(state: State) -> Self {
+        Transition {
+            next: Some(state),
+            ..Self::keep()
+        }
+    }
+
+    pub fn reply(mut self, reply: Reply) -> Self {
+        self.reply = Some(reply);
+        self
+    }
+
+    // the reply is ignored, the caller keeps waiting until the request is handled again
+    pub fn postpone(mut self) -> Self {
+        self.postpone = true;
+        self
+    }
+
+    pub fn state_timeout(mut self, timeout: Duration) -> Self {
+        self.state_timeout = Some(timeout);
+        self
+    }
+}
+
+type Handler<State, Data, Request, Reply> =
+    Box<dyn FnMut(&mut Data, &Event<Request>) -> Result<Transition<State, Reply>> + Send>;
+// called with the previous state, returns the state timeout to arm
+type EnterHandler<State, Data> =
+    Box<dyn FnMut(&mut Data, &State) -> Result<Option<Duration>> + Send>;
+
+pub struct StateMachineActor<State, Data, Request, Reply> {
+    state: State,
+    data: Data,
+    handlers: HashMap<State, Handler<State, Data, Request, Reply>>,
+    enter_handlers: HashMap<State, EnterHandler<State, Data>>,
+}
+
+impl<State, Data, Request, Reply> StateMachineActor<State, Data, Request, Reply>
+where
+    State: Eq + Hash + Clone,
+{
+    pub fn new(state: State, data: Data) -> Self {
+        StateMachineActor {
+            state,
+            data,
+            handlers: HashMap::new(),
+            enter_handlers: HashMap::new(),
+        }
+    }
+
+    pub fn state<F>(mut self, state: State, handler: F) -> Self
+    where
+        F: FnMut(&mut Data, &Event<Request>) -> Result<Transition<State, Reply>> + Send + 'static,
+    {
+        self.handlers.insert(state, Box::new(handler));
+        self
+    }
+
+    pub fn on_enter<F>(mut self, state: State, handler: F) -> Self
+    where
+        F: FnMut(&mut Data, &State) -> Result<Option<Duration>> + Send + 'static,
+    {
+        self.enter_handlers.insert(state, Box::new(handler));
+        self
+    }
+
+    fn handle_event(&mut self, event: &Event<Request>) -> Result<Transition<State, Reply>> {
+        match self.handlers.get_mut(&self.state) {
+            Some(handler) => handler(&mut self.data, event),
+            None => Err(anyhow!("no handler for current state")),
+        }
+    }
+
+    fn enter(&mut self, old: &State) -> Result<Option<Instant>> {
+        let handler = match self.enter_handlers.get_mut(&self.state) {
+            Some(handler) => handler,
+            None => return Ok(None),
+        };
+        let timeout = handler(&mut self.data, old)?;
+        Ok(timeout.map(|timeout| Instant::now() + timeout))
+    }
+}
+
+pub fn spawn<State, Data, Request, Reply>(
+    mut machine: StateMachineActor<State, Data, Request, Reply>,
+    mailbox: usize,
+) -> Pid<Request, Reply>
+where
+    State: Eq + Hash + Clone + Debug + Send + 'static,
+    Data: Send + 'static,
+    Request: Send + 'static,
+    Reply: Send + 'static,
+{
+    let (sender, mut receiver): (_, Receiver<ActorMessage<Request, Reply>>) =
+        mpsc::channel(mailbox);
+    tokio::spawn(async move {
+        let initial = machine.state.clone();
+        // the actor stops if the initial state can't be entered, callers see it's gone
+        let mut deadline = match machine.enter(&initial) {
+            Ok(deadline) => deadline,
+            Err(_) => return,
+        };
+        let mut postponed = VecDeque::new();
+        // postponed requests which are re-delivered before the mailbox
+        let mut pending: VecDeque<ActorMessage<Request, Reply>> = VecDeque::new();
+
+        loop {
+            let msg = match pending.pop_front() {
+                Some(msg) => Some(msg),
+                None => tokio::select! {
+                    msg = receiver.recv() => match msg {
+                        Some(msg) => Some(msg),
+                        None => break,
+                    },
+                    // the future is created even if the branch is disabled
+                    _ = time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => None,
+                },
+            };
+
+            let (event, mut reply_to, parent) = match msg {
+                Some(msg) => (Event::Call(msg.data), Some(msg.sender), msg.span),
+                None => {
+                    deadline = None;
+                    (Event::StateTimeout, None, Span::none())
+                }
+            };
+            let span = info_span!(parent: &parent, "handle_event", state = ?machine.state);
+            let _enter = span.enter();
+
+            let transition = match machine.handle_event(&event) {
+                Ok(transition) => transition,
+                Err(e) => {
+                    if let Some(sender) = reply_to {
+                        let _ = sender.send(Err(e));
+                    }
+                    continue;
+                }
+            };
+
+            if transition.postpone {
+                if let (Event::Call(data), Some(sender)) = (event, reply_to.take()) {
+                    postponed.push_back(ActorMessage {
+                        data,
+                        sender,
+                        span: parent,
+                        id: None,
+                    });
+                }
+            }
+
+            // the caller which caused the state change gets the error of the enter handler
+            let mut reply = transition.reply.map(Ok);
+            if let Some(next) = transition.next {
+                if next != machine.state {
+                    let old = std::mem::replace(&mut machine.state, next);
+                    while let Some(msg) = postponed.pop_back() {
+                        pending.push_front(msg);
+                    }
+                    deadline = match machine.enter(&old) {
+                        Ok(deadline) => deadline,
+                        Err(e) => {
+                            reply = Some(Err(e));
+                            None
+                        }
+                    };
+                }
+            }
+            if let (Some(sender), Some(reply)) = (reply_to, reply) {
+                let _ = sender.send(reply);
+            }
+
+            if let Some(timeout) = transition.state_timeout {
+                deadline = Some(Instant::now() + timeout);
+            }
+        }
+    });
+
+    Pid { sender }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    enum Door {
+        Locked,
+        Open,
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Request {
+        Code(&'static str),
+        Push,
+    }
+
+    #[derive(Default)]
+    struct Data {
+        pushed: usize,
+    }
+
+    fn door(open_for: Duration) -> Pid<Request, &'static str> {
+        let machine = StateMachineActor::new(Door::Locked, Data::default())
+            .state(Door::Locked, move |_data, event| match event {
+                Event::Call(Request::Code("1234")) => {
+                    Ok(Transition::next(Door::Open).reply("open"))
+                }
+                Event::Call(Request::Code(_)) => Ok(Transition::keep().reply("wrong code")),
+                Event::Call(Request::Push) => Ok(Transition::keep().postpone()),
+                Event::StateTimeout => Ok(Transition::keep()),
+            })
+            .state(Door::Open, |data, event| match event {
+                Event::Call(Request::Push) => {
+                    data.pushed += 1;
+                    Ok(Transition::keep().reply("pushed"))
+                }
+                Event::Call(Request::Code(_)) => Ok(Transition::keep().reply("already open")),
+                Event::StateTimeout => Ok(Transition::next(Door::Locked)),
+            })
+            .on_enter(Door::Open, move |_data, _old| Ok(Some(open_for)));
+        spawn(machine, 20)
+    }
+
+    #[tokio::test]
+    async fn transitions() {
+        let pid = door(Duration::from_secs(10));
+        assert_eq!(pid.send(Request::Code("0000")).await.unwrap(), "wrong code");
+        assert_eq!(pid.send(Request::Code("1234")).await.unwrap(), "open");
+        assert_eq!(
+            pid.send(Request::Code("1234")).await.unwrap(),
+            "already open"
+        );
+        assert_eq!(pid.send(Request::Push).await.unwrap(), "pushed");
+    }
+
+    #[tokio::test]
+    async fn state_timeout() {
+        let pid = door(Duration::from_millis(10));
+        assert_eq!(pid.send(Request::Code("1234")).await.unwrap(), "open");
+        time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(pid.send(Request::Code("0000")).await.unwrap(), "wrong code");
+    }
+
+    #[tokio::test]
+    async fn postpone() {
+        let pid = door(Duration::from_secs(10));
+        let pid1 = pid.clone();
+        let mut push = tokio::spawn(async move { pid1.send(Request::Push).await.unwrap() });
+        assert!(time::timeout(Duration::from_millis(10), &mut push)
+            .await
+            .is_err());
+
+        assert_eq!(pid.send(Request::Code("1234")).await.unwrap(), "open");
+        assert_eq!(push.await.unwrap(), "pushed");
+    }
+
+    #[tokio::test]
+    async fn missing_handler() {
+        let machine: StateMachineActor<Door, (), (), ()> = StateMachineActor::new(Door::Locked, ());
+        let pid = spawn(machine, 1);
+        assert!(pid.send(()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn state_timeout_from_handler() {
+        let machine = StateMachineActor::new(Door::Locked, ())
+            .state(Door::Locked, |_, event| match event {
+                Event::Call(_) => Ok(Transition::keep()
+                    .state_timeout(Duration::from_millis(10))
+                    .reply("locked")),
+                Event::StateTimeout => Ok(Transition::next(Door::Open)),
+            })
+            .state(Door::Open, |_, _| Ok(Transition::keep().reply("open")));
+        let pid = spawn(machine, 1);
+        assert_eq!(pid.send(Request::Push).await.unwrap(), "locked");
+        time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(pid.send(Request::Push).await.unwrap(), "open");
+    }
+
+    #[tokio::test]
+    async fn handler_errors() {
+        let machine = StateMachineActor::new(Door::Locked, ())
+            .state(Door::Locked, |_, event| match event {
+                Event::Call(Request::Push) => Err(anyhow!("jammed")),
+                _ => Ok(Transition::next(Door::Open).reply("open")),
+            })
+            .state(Door::Open, |_, _| Ok(Transition::keep().reply("open")))
+            .on_enter(Door::Open, |_, _| Err(anyhow!("stuck")));
+        let pid = spawn(machine, 1);
+        let err = pid.send(Request::Push).await.unwrap_err();
+        assert_eq!(err.to_string(), "jammed");
+        let err = pid.send(Request::Code("1234")).await.unwrap_err();
+        assert_eq!(err.to_string(), "stuck");
+    }
+}
@@ -30,3 +30,32 @@ async fn test_watch() {
     watch().await.unwrap();
     assert!(true);
 }
+
+// the same with the hand-built `sync::watch`
+async fn sync_watch() -> Result<()> {
+    let (tx, mut rx) = crate::sync::watch::channel("hello");
+    let mut rx2 = rx.clone();
+
+    let join_handle = tokio::spawn(async move {
+        if rx.changed().await.is_ok() {
+            println!("received 1 = {:?}", *rx.borrow());
+        }
+    });
+
+    let join_handle2 = tokio::task::spawn_blocking(move || {
+        if rx2.blocking_changed().is_ok() {
+            println!("received 2 = {:?}", *rx2.borrow());
+        }
+    });
+
+    tx.send("world")
+        .map_err(|_| anyhow::anyhow!("no receiver"))?;
+    join_handle.await?;
+    join_handle2.await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_sync_watch() {
+    sync_watch().await.unwrap();
+}